@@ -2,24 +2,70 @@
 
 precision highp float;
 
-const vec3 light = vec3(1, -1, 1);
 const vec3 lightColor = vec3(1, 1, 1);
 const vec4 fogColor = vec4(0.5, 0.5, 0.5, 0.5);
 const float phong_exp = 100.0;
+const vec3 snowColor = vec3(0.95, 0.95, 1.0);
+const vec3 iceColor = vec3(0.7, 0.85, 0.95);
+const vec3 luma = vec3(0.299, 0.587, 0.114);
+// Slope of the bumps per unit of texture luminance between texels.
+const float bumpStrength = 2.0;
 
 uniform bool fogEnabled;
+uniform bool solidColorEnabled;
+uniform vec4 solidColor;
+uniform bool biomesEnabled;
+uniform bool overlayEnabled;
 uniform sampler2D terrainTexture;
+uniform sampler2D biomeTexture;
+uniform sampler2D overlayTexture;
 uniform vec3 eye;
+// Direction towards the light, see `LIGHT` in `terrain.rs`.
+uniform vec3 light;
 
 in vec3 vNormal;
 in vec2 vTexCoord;
+in vec4 vTangent;
 in float vDepth;
+in vec2 vSnowCover;
 
 out vec4 fragColor;
 
+float luminance(vec2 uv) {
+    return dot(texture(terrainTexture, uv).rgb, luma);
+}
+
+// Normal bumped by the terrain texture read as a height map, in the tangent
+// space of `add_tangents_mut`.
+vec3 bumpedNormal(vec3 normal) {
+    vec3 tangent = normalize(vTangent.xyz - normal * dot(normal, vTangent.xyz));
+    vec3 bitangent = vTangent.w * cross(normal, tangent);
+    vec2 texel = 1.0 / vec2(textureSize(terrainTexture, 0));
+    float du = luminance(vTexCoord + vec2(texel.x, 0.0)) - luminance(vTexCoord - vec2(texel.x, 0.0));
+    float dv = luminance(vTexCoord + vec2(0.0, texel.y)) - luminance(vTexCoord - vec2(0.0, texel.y));
+    return normalize(normal - bumpStrength * (du * tangent + dv * bitangent));
+}
+
 void main() { 
     vec3 normal = normalize(vNormal);
     vec4 material = texture(terrainTexture, vTexCoord);
+    if (!solidColorEnabled) {
+        normal = bumpedNormal(normal);
+    }
+    if (biomesEnabled) {
+        // Keep the detail of the terrain texture on top of the biome color.
+        float detail = dot(material.rgb, luma);
+        material = vec4(texture(biomeTexture, vTexCoord).rgb * (0.5 + detail), 1.0);
+    }
+    // Snow over glacier ice, see `add_snow_mut`.
+    material.rgb = mix(material.rgb, iceColor, vSnowCover.y);
+    material.rgb = mix(material.rgb, snowColor, vSnowCover.x);
+    if (overlayEnabled) {
+        material = vec4(texture(overlayTexture, vTexCoord).rgb, 1.0);
+    }
+    if (solidColorEnabled) {
+        material = solidColor;
+    }
 
     vec3 l = normalize(light);
     float lambert = clamp(dot(l, normal), 0.0, 1.0);
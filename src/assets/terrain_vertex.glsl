@@ -3,18 +3,99 @@
 uniform mat4 m;
 uniform mat4 v;
 uniform mat4 p;
+// Compact grids store only heights and octahedral normals, see
+// `compact_terrain_geometry`.
+uniform bool compact;
+uniform int gridResolution;
+uniform float gridStep;
+// Geomipmapped patches of the shared grid mesh, see `geomipmap.rs`.
+uniform bool morphing;
+uniform highp sampler2D heightTexture;
+uniform ivec2 patchCorner;
+uniform int patchStride;
+uniform vec2 morphRange;
+uniform vec3 eye;
 
 in vec4 position;
 in vec3 normal;
 in vec2 texCoord;
+// See `add_tangents_mut`, zero where the attribute is missing.
+in vec4 tangent;
+in float height;
+in vec2 octNormal;
+in vec2 gridCoord;
+// Fractions of snow and ice cover, zero where the attribute is missing.
+in vec2 snowCover;
 
 out vec3 vNormal;
 out vec2 vTexCoord;
+out vec4 vTangent;
 out float vDepth;
+out vec2 vSnowCover;
+
+vec2 signNotZero(vec2 v) {
+    return vec2(v.x >= 0.0 ? 1.0 : -1.0, v.y >= 0.0 ? 1.0 : -1.0);
+}
+
+vec3 octahedralDecode(vec2 e) {
+    vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+    if (n.z < 0.0) {
+        n.xy = (1.0 - abs(n.yx)) * signNotZero(n.xy);
+    }
+    return normalize(n);
+}
+
+// Height of grid vertex (i, j); the texture has one row per i.
+float heightAt(ivec2 ij) {
+    ij = clamp(ij, ivec2(0), ivec2(gridResolution - 1));
+    return texelFetch(heightTexture, ij.yx, 0).r;
+}
+
+// Same as `morphed_height` in `geomipmap.rs`.
+float morphedHeight(ivec2 ij, int stride, float k) {
+    float fine = heightAt(ij);
+    bvec2 odd = equal((ij / stride) % 2, ivec2(1));
+    float coarse = fine;
+    if (odd.x && odd.y) {
+        coarse = 0.5 * (heightAt(ij + ivec2(stride, -stride)) + heightAt(ij + ivec2(-stride, stride)));
+    } else if (odd.x) {
+        coarse = 0.5 * (heightAt(ij - ivec2(stride, 0)) + heightAt(ij + ivec2(stride, 0)));
+    } else if (odd.y) {
+        coarse = 0.5 * (heightAt(ij - ivec2(0, stride)) + heightAt(ij + ivec2(0, stride)));
+    }
+    return mix(fine, coarse, k);
+}
 
 void main() {
-    gl_Position = p * v * m * position;
-    vNormal = normal;
-    vTexCoord = texCoord;
-    vDepth = -(v * m * position).z;
+    vec4 vertex = position;
+    vec3 vertexNormal = normal;
+    vec2 vertexTexCoord = texCoord;
+    // Every terrain mesh maps u along x and v against y, so meshes without
+    // tangents get those of a flat grid.
+    vec4 vertexTangent = dot(tangent.xyz, tangent.xyz) > 0.0 ? tangent : vec4(1.0, 0.0, 0.0, -1.0);
+    float origin = -0.5 * gridStep * float(gridResolution);
+    if (compact) {
+        // The ideal grid, ignoring how weathering moved the border vertices.
+        vec2 ij = vec2(gl_VertexID / gridResolution, gl_VertexID % gridResolution);
+        vertex = vec4(origin + gridStep * ij, height, 1.0);
+        vertexNormal = octahedralDecode(octNormal);
+    } else if (morphing) {
+        ivec2 ij = min(patchCorner + ivec2(gridCoord) * patchStride, ivec2(gridResolution - 1));
+        vec2 xy = origin + gridStep * vec2(ij);
+        float k = morphRange.y > morphRange.x
+            ? clamp((distance(xy, eye.xy) - morphRange.x) / (morphRange.y - morphRange.x), 0.0, 1.0)
+            : 0.0;
+        vertex = vec4(xy, morphedHeight(ij, patchStride, k), 1.0);
+        float zx = heightAt(ij + ivec2(1, 0)) - heightAt(ij - ivec2(1, 0));
+        float zy = heightAt(ij + ivec2(0, 1)) - heightAt(ij - ivec2(0, 1));
+        vertexNormal = vec3(-zx, -zy, 2.0 * gridStep);
+        vertexTexCoord = vec2(ij) / float(gridResolution);
+        vertexTexCoord.y = 1.0 - vertexTexCoord.y;
+    }
+    gl_Position = p * v * m * vertex;
+    vNormal = vertexNormal;
+    vTexCoord = vertexTexCoord;
+    vTangent = vertexTangent;
+    vDepth = -(v * m * vertex).z;
+    vSnowCover = snowCover;
 }
@@ -0,0 +1,9 @@
+use std::process;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(message) = rust_terrain::run_cli(&args) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,449 @@
+use nalgebra::{Vector2, Vector3};
+use std::fs;
+use std::io::{self, Write};
+
+use crate::terrain::{
+    analysis::{
+        aspect, false_color_image, grayscale_image, plan_curvature, profile_curvature, roughness,
+        slope, value_range,
+    },
+    biomes::{biome_preview, classify_biomes, BiomeTable},
+    climate::{compute_climate, ClimateParams},
+    contours::{contours_geojson, contours_svg, extract_contours, MAX_CONTOUR_LEVELS},
+    generate_heightfield, generate_tileable_heightfield,
+    geomipmap::{geomipmap_geometry, GeomipmapParams},
+    lakes::{detect_lakes, lakes_json},
+    pathfinding::{find_path, PathCost, Search},
+    raycast::raycast,
+    rivers::{carve_rivers_mut, rivers_geojson, FlowRouting, RiverParams},
+    roads::{build_road_mut, crossing_waypoints, RoadParams},
+    snow::{add_snow_mut, SnowParams},
+    statistics::{statistics_json, terrain_statistics},
+    terrain_geometry,
+    tin::{tin_geometry, TinParams},
+    Heightmap, TerrainNormals,
+};
+use crate::utils::{
+    geometry::NormalWeighting,
+    obj::encode_obj,
+    random::Rng,
+    simplify::{simplify, SimplifyParams},
+};
+
+const USAGE: &str = "\
+Usage: terrain <command> [options]
+
+Commands:
+  help                  this message
+  stats                 terrain statistics as JSON
+  lakes                 lakes filling the depressions as JSON
+  rivers                river network carved into the terrain as GeoJSON
+  contours              contour lines as SVG or GeoJSON
+  map                   analysis map as a PPM or PGM image
+  biomes                biome map as a PPM image
+  obj                   terrain mesh as a Wavefront OBJ file
+  road                  centerline of the road the viewer shows as GeoJSON
+  path                  cheapest route from --from to --to as GeoJSON
+  raycast               first point of the terrain seen from --eye looking
+                        along --direction, as JSON
+
+Options:
+  --seed <n>            seed of the generated terrain (default 0)
+  --tileable            terrain whose last row and column repeat the first
+                        ones, for copies that join without a seam
+  --road                carve the road the viewer shows into the terrain
+                        first, e.g. to export it with obj
+  --interval <m>        height between contour lines (default 10)
+  --layer <layer>       slope, aspect, plan-curvature, profile-curvature,
+                        roughness, flow, snow, temperature or
+                        precipitation for maps (default slope)
+  --routing <routing>   d8 or dinfinity flow routing for rivers and the flow
+                        map (default d8)
+  --format <format>     svg or geojson for contours (default svg), ppm or
+                        pgm for maps (default ppm)
+  --normals <normals>   area, angle or uniform weighted average of the face
+                        normals, or analytic or tileable from the
+                        heightfield (default area, tileable with --tileable)
+  --tin <m>             triangulate only the heightmap vertices needed to stay
+                        within this height of it, rather than all of them
+  --eye <x,y,z>         point rays are cast from; for obj, export the
+                        geomipmapped patches the viewer draws from there
+                        rather than the whole grid
+  --direction <x,y,z>   direction of the ray (default 0,0,-1, straight down)
+  --from <x,y>          start of the path (default -200,-200)
+  --to <x,y>            end of the path (default 200,200)
+  --search <search>     astar or dijkstra search for the path (default astar)
+  --simplify <m>        simplify the mesh, moving it by at most this much
+  --triangles <n>       simplify the mesh down to this many triangles
+  --output <file>       write to a file rather than to standard output
+";
+
+#[derive(Debug)]
+struct Options {
+    command: String,
+    seed: u64,
+    tileable: bool,
+    road: bool,
+    interval: f32,
+    layer: Option<String>,
+    routing: FlowRouting,
+    format: Option<String>,
+    normals: Option<TerrainNormals>,
+    tin: Option<TinParams>,
+    eye: Option<Vector3<f32>>,
+    direction: Vector3<f32>,
+    from: Vector2<f32>,
+    to: Vector2<f32>,
+    search: Search,
+    simplify: Option<SimplifyParams>,
+    output: Option<String>,
+}
+
+/// Generates a terrain and writes what `args` asks for, without the
+/// viewer. Returns a message for the user on failure.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse(args)?;
+    let mut rng = Rng::new(options.seed);
+    let (mut positions, normals) = if options.tileable {
+        (
+            generate_tileable_heightfield(&mut rng),
+            TerrainNormals::Tileable,
+        )
+    } else {
+        (
+            generate_heightfield(&mut rng),
+            TerrainNormals::Mesh(NormalWeighting::Area),
+        )
+    };
+    let road = if options.road || options.command == "road" {
+        let road = build_road_mut(
+            &mut positions,
+            &crossing_waypoints(),
+            &RoadParams::default(),
+        );
+        Some(road.ok_or("no road within the steepest slope allowed")?)
+    } else {
+        None
+    };
+
+    let bytes = match options.command.as_str() {
+        "help" => USAGE.as_bytes().to_vec(),
+        "stats" => {
+            let lakes = detect_lakes(&positions);
+            let water: Vec<bool> = lakes.lake_ids.iter().map(|id| id.is_some()).collect();
+            (statistics_json(&terrain_statistics(&positions, Some(&water))) + "\n").into_bytes()
+        }
+        "lakes" => (lakes_json(&positions, &detect_lakes(&positions)) + "\n").into_bytes(),
+        "rivers" => {
+            let params = RiverParams {
+                routing: options.routing,
+                ..RiverParams::default()
+            };
+            let rivers = carve_rivers_mut(&mut positions.clone(), &params);
+            (rivers_geojson(&rivers) + "\n").into_bytes()
+        }
+        "contours" => {
+            let contours = extract_contours(&positions, options.interval).ok_or_else(|| {
+                format!(
+                    "invalid interval {}: it must be positive and give at most {} levels",
+                    options.interval, MAX_CONTOUR_LEVELS
+                )
+            })?;
+            match options.format.as_deref().unwrap_or("svg") {
+                "svg" => contours_svg(&contours).into_bytes(),
+                "geojson" => (contours_geojson(&contours) + "\n").into_bytes(),
+                format => return Err(format!("unknown contour format {}", format)),
+            }
+        }
+        "map" => {
+            // Same ranges as the viewer's overlays, curvatures centered on 0.
+            let symmetric = |values: &[f32]| {
+                let (lo, hi) = value_range(values);
+                let c = f32::max(lo.abs(), hi.abs());
+                (-c, c)
+            };
+            let layer = options.layer.as_deref().unwrap_or("slope");
+            let (values, range) = match layer {
+                "slope" => (slope(&positions), (0.0, 60.0)),
+                "aspect" => (aspect(&positions), (0.0, 360.0)),
+                "plan-curvature" => {
+                    let values = plan_curvature(&positions);
+                    let range = symmetric(&values);
+                    (values, range)
+                }
+                "profile-curvature" => {
+                    let values = profile_curvature(&positions);
+                    let range = symmetric(&values);
+                    (values, range)
+                }
+                "roughness" => {
+                    let values = roughness(&positions);
+                    let range = value_range(&values);
+                    (values, range)
+                }
+                // Contributing areas span orders of magnitude.
+                "flow" => {
+                    let params = RiverParams {
+                        routing: options.routing,
+                        ..RiverParams::default()
+                    };
+                    let rivers = carve_rivers_mut(&mut positions.clone(), &params);
+                    let values: Vec<f32> = rivers.accumulation.iter().map(|a| a.log10()).collect();
+                    let range = value_range(&values);
+                    (values, range)
+                }
+                "temperature" | "precipitation" => {
+                    let climate = compute_climate(&positions, &ClimateParams::default());
+                    let values = match layer {
+                        "temperature" => climate.temperature,
+                        _ => climate.precipitation,
+                    };
+                    let range = value_range(&values);
+                    (values, range)
+                }
+                "snow" => {
+                    let params = SnowParams::default();
+                    let cover = add_snow_mut(&mut positions.clone(), &params);
+                    (cover.snow, (0.0, params.max_depth))
+                }
+                layer => return Err(format!("unknown layer {}", layer)),
+            };
+            match options.format.as_deref().unwrap_or("ppm") {
+                "ppm" => false_color_image(&values, range),
+                "pgm" => grayscale_image(&values, range),
+                format => return Err(format!("unknown map format {}", format)),
+            }
+        }
+        "biomes" => {
+            let climate = compute_climate(&positions, &ClimateParams::default());
+            biome_preview(&classify_biomes(
+                &positions,
+                &climate,
+                &BiomeTable::default(),
+            ))
+        }
+        "path" => {
+            let lakes = detect_lakes(&positions);
+            let water: Vec<bool> = lakes.lake_ids.iter().map(|id| id.is_some()).collect();
+            let path = find_path(
+                &positions,
+                Some(&water),
+                &options.from,
+                &options.to,
+                &PathCost::default(),
+                options.search,
+            )
+            .ok_or("no path between --from and --to")?;
+            (line_geojson(&path) + "\n").into_bytes()
+        }
+        "road" => (line_geojson(&road.unwrap_or_default()) + "\n").into_bytes(),
+        "raycast" => {
+            let eye = options.eye.ok_or("raycast needs --eye")?;
+            let hit = raycast(&positions, &eye, &options.direction, f32::INFINITY);
+            let json = match hit {
+                Some(hit) => format!(
+                    "{{\"point\": [{:.3}, {:.3}, {:.3}], \"normal\": [{:.3}, {:.3}, {:.3}], \
+                     \"triangle\": {}, \"distance\": {:.3}}}",
+                    hit.point.x,
+                    hit.point.y,
+                    hit.point.z,
+                    hit.normal.x,
+                    hit.normal.y,
+                    hit.normal.z,
+                    hit.triangle,
+                    hit.distance
+                ),
+                None => String::from("null"),
+            };
+            (json + "\n").into_bytes()
+        }
+        "obj" => {
+            let heightmap = Heightmap::from_positions(&positions);
+            let mut geometry = match (options.tin.as_ref(), options.eye.as_ref()) {
+                (Some(_), Some(_)) => return Err("choose either --tin or --eye".to_string()),
+                (Some(params), None) => tin_geometry(&heightmap, params),
+                (None, Some(eye)) => {
+                    geomipmap_geometry(&heightmap, eye, &GeomipmapParams::default())
+                }
+                (None, None) => terrain_geometry(&positions, options.normals.unwrap_or(normals)),
+            };
+            if let Some(params) = options.simplify.as_ref() {
+                geometry = simplify(&geometry, params).ok_or("cannot simplify the mesh")?;
+            }
+            encode_obj(&geometry)
+                .ok_or("cannot export the mesh")?
+                .into_bytes()
+        }
+        command => return Err(format!("unknown command {}\n\n{}", command, USAGE)),
+    };
+
+    match options.output {
+        Some(path) => fs::write(&path, bytes).map_err(|e| format!("{}: {}", path, e)),
+        None => io::stdout().write_all(&bytes).map_err(|e| e.to_string()),
+    }
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        command: String::new(),
+        seed: 0,
+        tileable: false,
+        road: false,
+        interval: 10.0,
+        layer: None,
+        routing: FlowRouting::D8,
+        format: None,
+        normals: None,
+        tin: None,
+        eye: None,
+        direction: -Vector3::z(),
+        from: Vector2::new(-200.0, -200.0),
+        to: Vector2::new(200.0, 200.0),
+        search: Search::AStar,
+        simplify: None,
+        output: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value()?;
+                options.seed = seed.parse().map_err(|_| format!("invalid seed {}", seed))?;
+            }
+            "--tileable" => options.tileable = true,
+            "--road" => options.road = true,
+            "--interval" => {
+                let interval = value()?;
+                // Whether there are too many levels depends on the terrain,
+                // so extract_contours checks the range.
+                options.interval = interval
+                    .parse()
+                    .map_err(|_| format!("invalid interval {}", interval))?;
+            }
+            "--layer" => options.layer = Some(value()?.clone()),
+            "--normals" => {
+                options.normals = Some(match value()?.as_str() {
+                    "area" => TerrainNormals::Mesh(NormalWeighting::Area),
+                    "angle" => TerrainNormals::Mesh(NormalWeighting::Angle),
+                    "uniform" => TerrainNormals::Mesh(NormalWeighting::Uniform),
+                    "analytic" => TerrainNormals::Analytic,
+                    "tileable" => TerrainNormals::Tileable,
+                    normals => return Err(format!("unknown normals {}", normals)),
+                })
+            }
+            "--tin" => {
+                let error = value()?;
+                options.tin = Some(TinParams {
+                    max_error: error
+                        .parse()
+                        .ok()
+                        .filter(|&error: &f32| error >= 0.0)
+                        .ok_or_else(|| format!("invalid error {}", error))?,
+                    ..TinParams::default()
+                });
+            }
+            "--eye" => {
+                let eye = value()?;
+                options.eye = Some(vector3(eye).ok_or_else(|| format!("invalid eye {}", eye))?);
+            }
+            "--direction" => {
+                let direction = value()?;
+                options.direction = vector3(direction)
+                    .filter(|d| d.norm() > 0.0)
+                    .ok_or_else(|| format!("invalid direction {}", direction))?;
+            }
+            "--from" | "--to" => {
+                let point = value()?;
+                let point = coordinates(point)
+                    .and_then(|c| match c[..] {
+                        [x, y] => Some(Vector2::new(x, y)),
+                        _ => None,
+                    })
+                    .ok_or_else(|| format!("invalid point {}", point))?;
+                if arg == "--from" {
+                    options.from = point;
+                } else {
+                    options.to = point;
+                }
+            }
+            "--search" => {
+                options.search = match value()?.as_str() {
+                    "astar" => Search::AStar,
+                    "dijkstra" => Search::Dijkstra,
+                    search => return Err(format!("unknown search {}", search)),
+                }
+            }
+            "--simplify" => {
+                let error = value()?;
+                options
+                    .simplify
+                    .get_or_insert_with(SimplifyParams::default)
+                    .max_error = error
+                    .parse()
+                    .ok()
+                    .filter(|&error: &f32| error >= 0.0)
+                    .ok_or_else(|| format!("invalid error {}", error))?;
+            }
+            "--triangles" => {
+                let triangles = value()?;
+                options
+                    .simplify
+                    .get_or_insert_with(|| SimplifyParams {
+                        max_error: f32::INFINITY,
+                        ..SimplifyParams::default()
+                    })
+                    .target_triangles = triangles
+                    .parse()
+                    .map_err(|_| format!("invalid triangle count {}", triangles))?;
+            }
+            "--routing" => {
+                options.routing = match value()?.as_str() {
+                    "d8" => FlowRouting::D8,
+                    "dinfinity" => FlowRouting::DInfinity,
+                    routing => return Err(format!("unknown routing {}", routing)),
+                }
+            }
+            "--format" => options.format = Some(value()?.clone()),
+            "--output" => options.output = Some(value()?.clone()),
+            "--help" | "-h" => options.command = String::from("help"),
+            _ if options.command.is_empty() && !arg.starts_with('-') => {
+                options.command = arg.clone()
+            }
+            _ => return Err(format!("unexpected argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+    if options.command.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(options)
+}
+
+/// GeoJSON feature of a 3D line string.
+fn line_geojson(points: &[Vector3<f32>]) -> String {
+    let coordinates: Vec<String> = points
+        .iter()
+        .map(|p| format!("[{:.2},{:.2},{:.2}]", p.x, p.y, p.z))
+        .collect();
+    format!(
+        "{{\"type\":\"Feature\",\"properties\":{{}},\
+         \"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}}}}",
+        coordinates.join(",")
+    )
+}
+
+/// Comma-separated coordinates such as `x,y,z`.
+fn coordinates(value: &str) -> Option<Vec<f32>> {
+    value.split(',').map(|c| c.trim().parse().ok()).collect()
+}
+
+/// Comma-separated `x,y,z` coordinates.
+fn vector3(value: &str) -> Option<Vector3<f32>> {
+    match coordinates(value)?[..] {
+        [x, y, z] => Some(Vector3::new(x, y, z)),
+        _ => None,
+    }
+}
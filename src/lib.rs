@@ -1,24 +1,64 @@
+mod cli;
 mod terrain;
 mod utils;
 
+pub use cli::run as run_cli;
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
-use nalgebra::{Matrix4, Point3, Rotation3, Unit, Vector3};
-use std::{cell::RefCell, convert::TryInto, f32::consts::PI, rc::Rc};
-use terrain::generate_terrain;
+use js_sys::Float32Array;
+use nalgebra::{Matrix4, Point3, Rotation3, Unit, Vector2, Vector3};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    convert::TryInto,
+    f32::consts::PI,
+    rc::Rc,
+};
+use terrain::{
+    analysis::{
+        aspect, false_colors, plan_curvature, profile_curvature, roughness, slope, value_range,
+    },
+    biomes::{biome_colors, classify_biomes, BiomeTable},
+    chunks::{chunk_at, chunks_around, generate_chunk, ChunkCache},
+    climate::{compute_climate, ClimateParams},
+    compact_terrain_geometry,
+    contours::{contour_geometry, extract_contours},
+    dunes::{add_dunes_mut, DuneParams},
+    generate_heightfield,
+    geomipmap::{patch_geometry, select_patches, GeomipmapParams},
+    lakes::{detect_lakes, lake_geometry},
+    lod::{build_quadtree, select_tiles, tile_geometry, QuadTree},
+    pathfinding::{find_path, PathCost, Search},
+    polyline_geometry,
+    raycast::raycast,
+    rivers::{carve_rivers_mut, RiverParams},
+    roads::{build_road_mut, crossing_waypoints, RoadParams},
+    snow::{add_snow_mut, SnowParams},
+    statistics::{statistics_json, terrain_statistics},
+    terrain_geometry,
+    visibility::viewshed,
+    Heightmap, TerrainNormals, LIGHT, RESOLUTION,
+};
 use utils::{
+    geometry::NormalWeighting,
+    optimize::{acmr, optimize_mut, optimize_vertex_cache, FIFO_CACHE_SIZE},
+    random::Rng,
     set_panic_hook,
-    web::{document, request_animation_frame, window},
-    webgl::{load_shaders, setup_geometry, Geometry},
+    web::{document, random_f32, request_animation_frame, window},
+    webgl::{
+        load_shaders, memory_report, memory_report_text, setup_geometry, BufferLayout, Geometry,
+        VertexAttrInfo,
+    },
 };
 use wasm_bindgen::{prelude::*, JsCast};
 use web_sys::{
-    Event, HtmlCanvasElement, HtmlImageElement, KeyboardEvent, WebGl2RenderingContext,
-    WebGlProgram, WebGlVertexArrayObject,
+    Event, HtmlCanvasElement, HtmlElement, HtmlImageElement, KeyboardEvent, WebGl2RenderingContext,
+    WebGlBuffer, WebGlProgram, WebGlTexture, WebGlVertexArrayObject,
 };
 
 #[derive(Debug)]
@@ -73,14 +113,188 @@ impl Camera {
     }
 }
 
+/// How the terrain surface is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TerrainMode {
+    /// The whole grid in one mesh.
+    Full,
+    /// Quadtree tiles chosen by screen-space error.
+    Quadtree,
+    /// Patches of one shared grid mesh morphing between levels.
+    Geomipmap,
+    /// Chunks of an unbounded terrain generated around the camera.
+    Infinite,
+}
+
+/// A geometry uploaded to the GPU, drawn either with the terrain texture or
+/// with a solid color.
+struct Mesh {
+    vao: WebGlVertexArrayObject,
+    buffers: Vec<WebGlBuffer>,
+    geometry: Geometry,
+    /// Primitive the indices describe, e.g. `TRIANGLES` or `LINES`.
+    mode: u32,
+    color: Option<[f32; 4]>,
+}
+
+impl Mesh {
+    fn new(
+        gl: &WebGl2RenderingContext,
+        program: &WebGlProgram,
+        mut geometry: Geometry,
+        mode: u32,
+        color: Option<[f32; 4]>,
+    ) -> Result<Mesh, JsValue> {
+        // Line meshes keep their index pairs in `triangles`, which would
+        // read as broken triangles.
+        if cfg!(debug_assertions) && mode == WebGl2RenderingContext::TRIANGLES {
+            for issue in geometry.repair_mut() {
+                log(&format!("repaired geometry: {}", issue));
+            }
+        }
+        let (vao, buffers) = setup_geometry(gl, program, &geometry, BufferLayout::Interleaved)?;
+        Ok(Mesh {
+            vao,
+            buffers,
+            geometry,
+            mode,
+            color,
+        })
+    }
+
+    /// Frees the mesh's GPU memory.
+    fn delete(self, gl: &WebGl2RenderingContext) {
+        gl.delete_vertex_array(Some(&self.vao));
+        for buffer in self.buffers.iter() {
+            gl.delete_buffer(Some(buffer));
+        }
+    }
+}
+
+/// The terrain with landforms carved into it, drawn with the full mesh in
+/// place of the plain terrain.
+struct TerrainEdit {
+    terrain: Mesh,
+    /// Lines tracing the landforms, e.g. river courses.
+    lines: Option<Mesh>,
+}
+
+impl TerrainEdit {
+    fn new(
+        gl: &WebGl2RenderingContext,
+        program: &WebGlProgram,
+        terrain: Geometry,
+        lines: Option<(Geometry, [f32; 4])>,
+    ) -> Result<TerrainEdit, JsValue> {
+        Ok(TerrainEdit {
+            terrain: Mesh::new(
+                gl,
+                program,
+                terrain,
+                WebGl2RenderingContext::TRIANGLES,
+                None,
+            )?,
+            lines: lines
+                .map(|(geometry, color)| {
+                    Mesh::new(
+                        gl,
+                        program,
+                        geometry,
+                        WebGl2RenderingContext::LINES,
+                        Some(color),
+                    )
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/// Grid of an edited heightfield with cache-optimized triangles, and with
+/// tangents for the bumps drawn from the terrain texture, as half floats.
+fn edited_terrain(positions: &[Vector3<f32>]) -> Geometry {
+    let mut terrain = terrain_geometry(positions, TERRAIN_NORMALS);
+    terrain.triangles = optimize_vertex_cache(&terrain.triangles, terrain.vertex_count());
+    let tangents = terrain
+        .attributes
+        .get("tangent")
+        .and_then(|attr| attr.data.as_f32())
+        .map(|data| VertexAttrInfo::f16("tangent", 4, data));
+    if let Some(tangents) = tangents {
+        terrain.attributes.set(tangents);
+    }
+    terrain
+}
+
+/// Everything drawn each frame.
+struct Scene {
+    /// Heightfield the meshes were built from, for collision.
+    positions: Vec<Vector3<f32>>,
+    terrain: Mesh,
+    terrain_mode: Cell<TerrainMode>,
+    heightmap: Heightmap,
+    lod: QuadTree,
+    /// Tiles uploaded so far, by quadtree node.
+    lod_tiles: RefCell<HashMap<usize, Mesh>>,
+    /// Shared mesh of the geomipmapped patches, which read their heights
+    /// from the texture in `HEIGHT_SLOT`.
+    patch: Mesh,
+    geomipmap: GeomipmapParams,
+    /// Seed of the unbounded terrain drawn in `TerrainMode::Infinite`.
+    world_seed: u64,
+    /// Chunks uploaded so far, with their heights for collision.
+    chunks: RefCell<ChunkCache<(Mesh, Heightmap)>>,
+    edits: Vec<TerrainEdit>,
+    /// Index into `edits` of the terrain currently shown.
+    edit: Cell<Option<usize>>,
+    /// False-color analysis maps that can be laid over the terrain.
+    overlays: Vec<WebGlTexture>,
+    /// Index into `overlays` of the map currently shown.
+    overlay: Cell<Option<usize>>,
+    lakes: Mesh,
+    lakes_visible: Cell<bool>,
+    contours: Mesh,
+    contours_visible: Cell<bool>,
+    path: Mesh,
+    path_visible: Cell<bool>,
+    /// Panel listing the terrain statistics.
+    stats_panel: HtmlElement,
+}
+
 const VERTEX_SHADER_URL: &str = "terrain_vertex.glsl";
 const FRAGMENT_SHADER_URL: &str = "terrain_fragment.glsl";
 const BOX_SIZE: f32 = 500.0;
 const NEAR_PLANE: f32 = 10.0;
 const FAR_PLANE: f32 = 4.0 * BOX_SIZE;
 const FOCAL_LENGTH: f32 = 200.0;
+const FIELD_OF_VIEW: f32 = PI / 4.0;
 const FLIGHT_SPEED: f32 = 2.0;
 const TURNING_SPEED: f32 = 0.01;
+const WATER_COLOR: [f32; 4] = [0.2, 0.4, 0.7, 1.0];
+const BIOME_SLOT: u32 = 1;
+const OVERLAY_SLOT: u32 = 2;
+const HEIGHT_SLOT: u32 = 3;
+/// Height above the highest peak of the observer of the viewshed overlay.
+const OBSERVER_HEIGHT: f32 = 10.0;
+/// Closest the camera can fly to the terrain surface.
+const COLLISION_MARGIN: f32 = 5.0;
+const CONTOUR_INTERVAL: f32 = 10.0;
+const CONTOUR_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+const PATH_COLOR: [f32; 4] = [0.9, 0.1, 0.1, 1.0];
+const LOD_TILE_CELLS: usize = 16;
+/// Largest screen-space error of the LOD tiles, in pixels.
+const LOD_MAX_ERROR: f32 = 2.0;
+const PATCH_CELLS: usize = 8;
+const ROAD_COLOR: [f32; 4] = [0.3, 0.25, 0.2, 1.0];
+const RIVER_COLOR: [f32; 4] = [0.1, 0.3, 0.8, 1.0];
+/// Glacier ice thickness drawn as fully icy.
+const ICE_COVER_DEPTH: f32 = 5.0;
+const TERRAIN_NORMALS: TerrainNormals = TerrainNormals::Mesh(NormalWeighting::Area);
+/// Chunks drawn beyond the one under the camera, on each side.
+const CHUNK_RADIUS: i32 = 3;
+/// Chunks kept uploaded, at least the `(2 * CHUNK_RADIUS + 1)^2` drawn.
+const CHUNK_BUDGET: usize = 64;
+/// Chunks generated per frame, so that crossing into new ones doesn't stall.
+const CHUNKS_PER_FRAME: usize = 1;
 
 #[wasm_bindgen(start)]
 pub async fn start() -> Result<(), JsValue> {
@@ -101,18 +315,209 @@ pub async fn start() -> Result<(), JsValue> {
     gl.enable(WebGl2RenderingContext::DEPTH_TEST);
 
     let program = load_shaders(&gl, VERTEX_SHADER_URL, FRAGMENT_SHADER_URL).await?;
-    let geometry = generate_terrain();
-    let vao = setup_geometry(&gl, &program, &geometry)?;
+    let positions = generate_heightfield(&mut Rng::new(random_seed()));
+    let lake_map = detect_lakes(&positions);
+    let climate = compute_climate(&positions, &ClimateParams::default());
+    let biomes = classify_biomes(&positions, &climate, &BiomeTable::default());
+
+    let symmetric = |values: &[f32]| {
+        let (lo, hi) = value_range(values);
+        let c = f32::max(lo.abs(), hi.abs());
+        (-c, c)
+    };
+    let plan = plan_curvature(&positions);
+    let profile = profile_curvature(&positions);
+    let rough = roughness(&positions);
+    let peak = positions
+        .iter()
+        .fold(positions[0], |a, &b| if b.z > a.z { b } else { a });
+    // The peak is a vertex, so always on the map.
+    let visible: Vec<f32> = viewshed(&positions, &peak.xy(), OBSERVER_HEIGHT, 0.0)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| v as u8 as f32)
+        .collect();
+    let overlays = [
+        (slope(&positions), (0.0, 60.0)),
+        (aspect(&positions), (0.0, 360.0)),
+        (plan.clone(), symmetric(&plan)),
+        (profile.clone(), symmetric(&profile)),
+        (rough.clone(), value_range(&rough)),
+        (visible, (0.0, 1.0)),
+    ]
+    .iter()
+    .map(|(values, range)| create_map_texture(&gl, OVERLAY_SLOT, &false_colors(values, *range)))
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let water: Vec<bool> = lake_map.lake_ids.iter().map(|id| id.is_some()).collect();
+    let stats_panel = document()
+        .get_element_by_id("stats")
+        .ok_or("element stats not found")?
+        .dyn_into::<HtmlElement>()?;
+    let mut terrain = compact_terrain_geometry(&positions, TERRAIN_NORMALS);
+    let full_terrain = terrain_geometry(&positions, TERRAIN_NORMALS);
+    // Compact vertices are found by index, so only the triangles move.
+    let row_major_acmr = acmr(&terrain.triangles, FIFO_CACHE_SIZE);
+    terrain.triangles = optimize_vertex_cache(&terrain.triangles, terrain.vertex_count());
+    stats_panel.set_text_content(Some(&format!(
+        "{}\n\n{}ACMR: {:.2} row-major, {:.2} optimized\n",
+        statistics_json(&terrain_statistics(&positions, Some(&water))),
+        memory_report_text(&[
+            (
+                "separate",
+                memory_report(&full_terrain, BufferLayout::Separate)
+            ),
+            (
+                "interleaved",
+                memory_report(&full_terrain, BufferLayout::Interleaved)
+            ),
+            (
+                "compact, on the ideal grid",
+                memory_report(&terrain, BufferLayout::Interleaved)
+            ),
+        ]),
+        row_major_acmr,
+        acmr(&terrain.triangles, FIFO_CACHE_SIZE)
+    )));
+    let path = find_path(
+        &positions,
+        Some(&water),
+        &Vector2::new(-0.4 * BOX_SIZE, -0.4 * BOX_SIZE),
+        &Vector2::new(0.4 * BOX_SIZE, 0.4 * BOX_SIZE),
+        &PathCost::default(),
+        Search::AStar,
+    );
+
+    let heightmap = Heightmap::from_positions(&positions);
+    let lod = build_quadtree(&heightmap, LOD_TILE_CELLS);
+    let geomipmap = GeomipmapParams {
+        patch_cells: PATCH_CELLS,
+        ..GeomipmapParams::default()
+    };
+    load_height_texture(&gl, &program, &heightmap)?;
+
+    let mut lakes = lake_geometry(&positions, &lake_map);
+    optimize_mut(&mut lakes);
+
+    let mut river_terrain = positions.clone();
+    let rivers = carve_rivers_mut(&mut river_terrain, &RiverParams::default());
+    let mut road_terrain = positions.clone();
+    let road = build_road_mut(
+        &mut road_terrain,
+        &crossing_waypoints(),
+        &RoadParams::default(),
+    );
+    let mut snow_terrain = positions.clone();
+    let snow_params = SnowParams::default();
+    let cover = add_snow_mut(&mut snow_terrain, &snow_params);
+    let mut snowy = edited_terrain(&snow_terrain);
+    snowy.attributes.set(VertexAttrInfo::unorm8(
+        "snowCover",
+        2,
+        &cover
+            .snow
+            .iter()
+            .zip(cover.ice.iter())
+            .flat_map(|(snow, ice)| vec![snow / snow_params.max_depth, ice / ICE_COVER_DEPTH])
+            .collect::<Vec<_>>(),
+    ));
+    let mut dune_terrain = positions.clone();
+    add_dunes_mut(&mut dune_terrain, &DuneParams::default());
+    let edits = vec![
+        TerrainEdit::new(
+            &gl,
+            &program,
+            edited_terrain(&river_terrain),
+            Some((polyline_geometry(&rivers.polylines), RIVER_COLOR)),
+        )?,
+        TerrainEdit::new(
+            &gl,
+            &program,
+            edited_terrain(&road_terrain),
+            road.map(|road| (polyline_geometry(&[road]), ROAD_COLOR)),
+        )?,
+        TerrainEdit::new(&gl, &program, snowy, None)?,
+        TerrainEdit::new(&gl, &program, edited_terrain(&dune_terrain), None)?,
+    ];
+
+    let scene = Rc::new(Scene {
+        terrain: Mesh::new(
+            &gl,
+            &program,
+            terrain,
+            WebGl2RenderingContext::TRIANGLES,
+            None,
+        )?,
+        heightmap,
+        lod,
+        lod_tiles: RefCell::new(HashMap::new()),
+        terrain_mode: Cell::new(TerrainMode::Full),
+        patch: Mesh::new(
+            &gl,
+            &program,
+            patch_geometry(geomipmap.patch_cells),
+            WebGl2RenderingContext::TRIANGLES,
+            None,
+        )?,
+        geomipmap,
+        world_seed: random_seed(),
+        chunks: RefCell::new(ChunkCache::new(CHUNK_BUDGET)),
+        edits,
+        edit: Cell::new(None),
+        overlays,
+        overlay: Cell::new(None),
+        lakes: Mesh::new(
+            &gl,
+            &program,
+            lakes,
+            WebGl2RenderingContext::TRIANGLES,
+            Some(WATER_COLOR),
+        )?,
+        lakes_visible: Cell::new(false),
+        contours: Mesh::new(
+            &gl,
+            &program,
+            contour_geometry(&extract_contours(&positions, CONTOUR_INTERVAL).unwrap_or_default()),
+            WebGl2RenderingContext::LINES,
+            Some(CONTOUR_COLOR),
+        )?,
+        contours_visible: Cell::new(false),
+        path: Mesh::new(
+            &gl,
+            &program,
+            polyline_geometry(&path.into_iter().collect::<Vec<_>>()),
+            WebGl2RenderingContext::LINES,
+            Some(PATH_COLOR),
+        )?,
+        path_visible: Cell::new(false),
+        stats_panel,
+        positions,
+    });
 
-    setup_event_listeners(&gl, &pressed, &proj_mat)?;
+    setup_event_listeners(&gl, &pressed, &proj_mat, &scene)?;
     load_texture(gl.clone(), program.clone())?;
+    load_biome_texture(&gl, &program, &biome_colors(&biomes))?;
+    gl.uniform1i(
+        gl.get_uniform_location(&program, "overlayTexture").as_ref(),
+        OVERLAY_SLOT.try_into().unwrap(),
+    );
+    gl.uniform1i(
+        gl.get_uniform_location(&program, "gridResolution").as_ref(),
+        RESOLUTION,
+    );
+    gl.uniform1f(
+        gl.get_uniform_location(&program, "gridStep").as_ref(),
+        BOX_SIZE / RESOLUTION as f32,
+    );
+    gl.uniform3fv_with_f32_array(
+        gl.get_uniform_location(&program, "light").as_ref(),
+        LIGHT.as_slice(),
+    );
 
     let raf_cb = Rc::new(RefCell::new(None as Option<Closure<dyn FnMut(f64)>>));
     let raf_cb_ = Rc::clone(&raf_cb);
     *raf_cb.borrow_mut() = Some(Closure::new(move |ms| {
-        draw(
-            &gl, &program, &vao, &geometry, &pressed, &camera, &proj_mat, ms,
-        );
+        draw(&gl, &program, &scene, &pressed, &camera, &proj_mat, ms);
         request_animation_frame(raf_cb_.borrow().as_ref().expect("RAF callback is defined"))
             .expect("RAF failed");
     }));
@@ -122,6 +527,12 @@ pub async fn start() -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Seed for the terrain generators, which draw from `Rng` rather than
+/// `Math.random` so that the CLI can replay them.
+fn random_seed() -> u64 {
+    (random_f32() as f64 * u32::MAX as f64) as u64
+}
+
 fn load_texture(gl: WebGl2RenderingContext, program: WebGlProgram) -> Result<(), JsValue> {
     let slot = 0;
     let texture = gl.create_texture().ok_or("failed to create texture")?;
@@ -131,12 +542,12 @@ fn load_texture(gl: WebGl2RenderingContext, program: WebGlProgram) -> Result<(),
     gl.tex_parameteri(
         WebGl2RenderingContext::TEXTURE_2D,
         WebGl2RenderingContext::TEXTURE_WRAP_S,
-        WebGl2RenderingContext::CLAMP_TO_EDGE.try_into().unwrap(),
+        WebGl2RenderingContext::MIRRORED_REPEAT.try_into().unwrap(),
     );
     gl.tex_parameteri(
         WebGl2RenderingContext::TEXTURE_2D,
         WebGl2RenderingContext::TEXTURE_WRAP_T,
-        WebGl2RenderingContext::CLAMP_TO_EDGE.try_into().unwrap(),
+        WebGl2RenderingContext::MIRRORED_REPEAT.try_into().unwrap(),
     );
     gl.tex_parameteri(
         WebGl2RenderingContext::TEXTURE_2D,
@@ -159,6 +570,9 @@ fn load_texture(gl: WebGl2RenderingContext, program: WebGlProgram) -> Result<(),
 
     let onload = Closure::<dyn FnMut(Event)>::new(move |e: Event| {
         let image_el = &e.target().unwrap().dyn_into::<HtmlImageElement>().unwrap();
+        // Other textures may have been bound since, so rebind this one.
+        gl.active_texture(WebGl2RenderingContext::TEXTURE0 + slot);
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
         gl.tex_image_2d_with_u32_and_u32_and_html_image_element(
             WebGl2RenderingContext::TEXTURE_2D,
             0,
@@ -180,6 +594,120 @@ fn load_texture(gl: WebGl2RenderingContext, program: WebGlProgram) -> Result<(),
     Ok(())
 }
 
+fn load_biome_texture(
+    gl: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    colors: &[u8],
+) -> Result<(), JsValue> {
+    create_map_texture(gl, BIOME_SLOT, colors)?;
+    gl.use_program(Some(program));
+    gl.uniform1i(
+        gl.get_uniform_location(program, "biomeTexture").as_ref(),
+        BIOME_SLOT.try_into().unwrap(),
+    );
+    gl.uniform1i(
+        gl.get_uniform_location(program, "biomesEnabled").as_ref(),
+        1,
+    );
+    Ok(())
+}
+
+/// Uploads the heights as a float texture, one row per `i`, for the
+/// geomipmapped patches to fetch from.
+fn load_height_texture(
+    gl: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    heightmap: &Heightmap,
+) -> Result<(), JsValue> {
+    let texture = gl.create_texture().ok_or("failed to create texture")?;
+    gl.active_texture(WebGl2RenderingContext::TEXTURE0 + HEIGHT_SLOT);
+    gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+    // Float textures can't be filtered, and are only read with texelFetch.
+    for &param in [
+        WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+        WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+    ]
+    .iter()
+    {
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            param,
+            WebGl2RenderingContext::NEAREST.try_into().unwrap(),
+        );
+    }
+    let size: i32 = heightmap.size.try_into().unwrap();
+    unsafe {
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            WebGl2RenderingContext::R32F.try_into().unwrap(),
+            size,
+            size,
+            0,
+            WebGl2RenderingContext::RED,
+            WebGl2RenderingContext::FLOAT,
+            Some(&Float32Array::view(&heightmap.heights)),
+        )?;
+    }
+    gl.use_program(Some(program));
+    gl.uniform1i(
+        gl.get_uniform_location(program, "heightTexture").as_ref(),
+        HEIGHT_SLOT.try_into().unwrap(),
+    );
+    Ok(())
+}
+
+/// Uploads per-vertex RGB colors, laid out like the terrain texture, and
+/// leaves the texture bound to `slot`.
+fn create_map_texture(
+    gl: &WebGl2RenderingContext,
+    slot: u32,
+    colors: &[u8],
+) -> Result<WebGlTexture, JsValue> {
+    let texture = gl.create_texture().ok_or("failed to create texture")?;
+    gl.active_texture(WebGl2RenderingContext::TEXTURE0 + slot);
+    gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+
+    for &param in [
+        WebGl2RenderingContext::TEXTURE_WRAP_S,
+        WebGl2RenderingContext::TEXTURE_WRAP_T,
+    ]
+    .iter()
+    {
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            param,
+            WebGl2RenderingContext::CLAMP_TO_EDGE.try_into().unwrap(),
+        );
+    }
+    for &param in [
+        WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+        WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+    ]
+    .iter()
+    {
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            param,
+            WebGl2RenderingContext::LINEAR.try_into().unwrap(),
+        );
+    }
+
+    gl.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        WebGl2RenderingContext::TEXTURE_2D,
+        0,
+        WebGl2RenderingContext::RGB8.try_into().unwrap(),
+        RESOLUTION,
+        RESOLUTION,
+        0,
+        WebGl2RenderingContext::RGB,
+        WebGl2RenderingContext::UNSIGNED_BYTE,
+        Some(colors),
+    )?;
+    Ok(texture)
+}
+
 fn reset_aspect_ratio(
     gl: &WebGl2RenderingContext,
     proj_mat: &Rc<RefCell<Matrix4<f32>>>,
@@ -204,7 +732,7 @@ fn reset_aspect_ratio(
 
     *proj_mat.borrow_mut() = Matrix4::new_perspective(
         canvas.width() as f32 / canvas.height() as f32,
-        PI / 4.0,
+        FIELD_OF_VIEW,
         NEAR_PLANE,
         FAR_PLANE,
     );
@@ -227,8 +755,7 @@ fn reset_aspect_ratio(
 fn draw(
     gl: &WebGl2RenderingContext,
     program: &WebGlProgram,
-    vao: &WebGlVertexArrayObject,
-    geometry: &Geometry,
+    scene: &Scene,
     pressed: &Rc<RefCell<PressedKeys>>,
     camera: &Rc<RefCell<Camera>>,
     proj_mat: &Rc<RefCell<Matrix4<f32>>>,
@@ -237,51 +764,72 @@ fn draw(
     gl.clear_color(0.5, 0.5, 0.5, 0.5);
     gl.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT);
     gl.use_program(Some(program));
-    gl.bind_vertex_array(Some(vao));
 
     let pressed = &mut *pressed.borrow_mut();
     let camera = &mut *camera.borrow_mut();
 
-    if pressed.w {
-        camera.eye += FLIGHT_SPEED * camera.forward;
+    let movement = if pressed.w {
+        FLIGHT_SPEED * camera.forward
     } else if pressed.s {
-        camera.eye -= FLIGHT_SPEED * camera.forward;
+        -FLIGHT_SPEED * camera.forward
     } else if pressed.d {
-        camera.eye += FLIGHT_SPEED * camera.right;
+        FLIGHT_SPEED * camera.right
     } else if pressed.a {
-        camera.eye -= FLIGHT_SPEED * camera.right;
+        -FLIGHT_SPEED * camera.right
+    } else {
+        Vector3::zeros()
+    };
+    let blocked = if scene.terrain_mode.get() == TerrainMode::Infinite {
+        let next = camera.eye + movement;
+        scene
+            .chunks
+            .borrow_mut()
+            .get(chunk_at(next.x, next.y))
+            .and_then(|(_, heightmap)| heightmap.height_at(next.x, next.y))
+            .is_some_and(|z| next.z < z + COLLISION_MARGIN)
+    } else {
+        raycast(
+            &scene.positions,
+            &camera.eye.coords,
+            &movement,
+            FLIGHT_SPEED + COLLISION_MARGIN,
+        )
+        .is_some()
+    };
+    if !blocked {
+        camera.eye += movement;
     }
 
     if pressed.up {
-        let axis = Unit::new_normalize(camera.right.clone());
+        let axis = Unit::new_normalize(camera.right);
         let rot = Rotation3::from_axis_angle(&axis, TURNING_SPEED);
         camera.up = rot * camera.up;
         camera.forward = rot * camera.forward;
     } else if pressed.down {
-        let axis = Unit::new_normalize(camera.right.clone());
+        let axis = Unit::new_normalize(camera.right);
         let rot = Rotation3::from_axis_angle(&axis, -TURNING_SPEED);
         camera.up = rot * camera.up;
         camera.forward = rot * camera.forward;
     } else if pressed.left {
-        let axis = Unit::new_normalize(camera.up.clone());
+        let axis = Unit::new_normalize(camera.up);
         let rot = Rotation3::from_axis_angle(&axis, TURNING_SPEED);
         camera.right = rot * camera.right;
         camera.forward = rot * camera.forward;
     } else if pressed.right {
-        let axis = Unit::new_normalize(camera.up.clone());
+        let axis = Unit::new_normalize(camera.up);
         let rot = Rotation3::from_axis_angle(&axis, -TURNING_SPEED);
         camera.right = rot * camera.right;
         camera.forward = rot * camera.forward;
     }
 
     gl.uniform3fv_with_f32_array(
-        gl.get_uniform_location(&program, "eye").as_ref(),
+        gl.get_uniform_location(program, "eye").as_ref(),
         (camera.eye - Point3::origin()).as_ref(),
     );
 
     let model_mat = Matrix4::<f32>::identity();
     gl.uniform_matrix4fv_with_f32_array(
-        gl.get_uniform_location(&program, "m").as_ref(),
+        gl.get_uniform_location(program, "m").as_ref(),
         false,
         model_mat.as_slice(),
     );
@@ -289,22 +837,176 @@ fn draw(
     let target = camera.eye + FOCAL_LENGTH * camera.forward;
     let view_mat = Matrix4::look_at_rh(&camera.eye, &target, &camera.up);
     gl.uniform_matrix4fv_with_f32_array(
-        gl.get_uniform_location(&program, "v").as_ref(),
+        gl.get_uniform_location(program, "v").as_ref(),
         false,
         view_mat.as_slice(),
     );
 
     gl.uniform_matrix4fv_with_f32_array(
-        gl.get_uniform_location(&program, "p").as_ref(),
+        gl.get_uniform_location(program, "p").as_ref(),
         false,
         proj_mat.borrow().as_slice(),
     );
 
-    gl.uniform1i(gl.get_uniform_location(&program, "fogEnabled").as_ref(), 1);
+    gl.uniform1i(gl.get_uniform_location(program, "fogEnabled").as_ref(), 1);
+
+    gl.uniform1i(
+        gl.get_uniform_location(program, "overlayEnabled").as_ref(),
+        scene.overlay.get().is_some() as i32,
+    );
+    if let Some(k) = scene.overlay.get() {
+        gl.active_texture(WebGl2RenderingContext::TEXTURE0 + OVERLAY_SLOT);
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&scene.overlays[k]));
+    }
+
+    gl.uniform1i(gl.get_uniform_location(program, "morphing").as_ref(), 0);
+    let edit = scene.edit.get().map(|k| &scene.edits[k]);
+    match scene.terrain_mode.get() {
+        TerrainMode::Full => match edit {
+            Some(edit) => {
+                draw_mesh(gl, program, &edit.terrain);
+                if let Some(lines) = edit.lines.as_ref() {
+                    draw_mesh(gl, program, lines);
+                }
+            }
+            None => draw_mesh(gl, program, &scene.terrain),
+        },
+        TerrainMode::Quadtree => draw_tiles(gl, program, scene, camera),
+        TerrainMode::Geomipmap => draw_patches(gl, program, scene, camera),
+        // Lakes, contours and the path belong to the bounded terrain.
+        TerrainMode::Infinite => return draw_chunks(gl, program, scene, camera),
+    }
+    if scene.lakes_visible.get() {
+        draw_mesh(gl, program, &scene.lakes);
+    }
+    if scene.contours_visible.get() {
+        draw_mesh(gl, program, &scene.contours);
+    }
+    if scene.path_visible.get() {
+        draw_mesh(gl, program, &scene.path);
+    }
+}
+
+fn draw_tiles(gl: &WebGl2RenderingContext, program: &WebGlProgram, scene: &Scene, camera: &Camera) {
+    let pixels_per_radian =
+        gl.drawing_buffer_height() as f32 / (2.0 * f32::tan(0.5 * FIELD_OF_VIEW));
+    let tiles = select_tiles(
+        &scene.lod,
+        &scene.heightmap,
+        &camera.eye.coords,
+        pixels_per_radian,
+        LOD_MAX_ERROR,
+    );
+    let mut uploaded = scene.lod_tiles.borrow_mut();
+    for id in tiles {
+        let tile = uploaded.entry(id).or_insert_with(|| {
+            Mesh::new(
+                gl,
+                program,
+                tile_geometry(&scene.lod, &scene.heightmap, id),
+                WebGl2RenderingContext::TRIANGLES,
+                None,
+            )
+            .expect("failed to upload terrain tile")
+        });
+        draw_mesh(gl, program, tile);
+    }
+}
+
+fn draw_patches(
+    gl: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    scene: &Scene,
+    camera: &Camera,
+) {
+    gl.uniform1i(gl.get_uniform_location(program, "morphing").as_ref(), 1);
+    for patch in select_patches(&scene.heightmap, &camera.eye.coords, &scene.geomipmap) {
+        gl.uniform2i(
+            gl.get_uniform_location(program, "patchCorner").as_ref(),
+            patch.i0 as i32,
+            patch.j0 as i32,
+        );
+        gl.uniform1i(
+            gl.get_uniform_location(program, "patchStride").as_ref(),
+            patch.stride as i32,
+        );
+        gl.uniform2f(
+            gl.get_uniform_location(program, "morphRange").as_ref(),
+            patch.morph_range.0,
+            patch.morph_range.1,
+        );
+        draw_mesh(gl, program, &scene.patch);
+    }
+    gl.uniform1i(gl.get_uniform_location(program, "morphing").as_ref(), 0);
+}
 
+fn draw_chunks(
+    gl: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    scene: &Scene,
+    camera: &Camera,
+) {
+    // The biome and overlay maps only cover the bounded terrain.
+    gl.uniform1i(
+        gl.get_uniform_location(program, "biomesEnabled").as_ref(),
+        0,
+    );
+    gl.uniform1i(
+        gl.get_uniform_location(program, "overlayEnabled").as_ref(),
+        0,
+    );
+
+    let mut chunks = scene.chunks.borrow_mut();
+    let mut generated = 0;
+    for coord in chunks_around(chunk_at(camera.eye.x, camera.eye.y), CHUNK_RADIUS) {
+        if chunks.get(coord).is_none() {
+            if generated == CHUNKS_PER_FRAME {
+                continue;
+            }
+            let chunk = generate_chunk(scene.world_seed, coord);
+            let mesh = Mesh::new(
+                gl,
+                program,
+                chunk.geometry,
+                WebGl2RenderingContext::TRIANGLES,
+                None,
+            )
+            .expect("failed to upload terrain chunk");
+            chunks.insert(coord, (mesh, chunk.heightmap));
+            generated += 1;
+        }
+        if let Some((mesh, _)) = chunks.get(coord) {
+            draw_mesh(gl, program, mesh);
+        }
+    }
+    for (mesh, _) in chunks.evict() {
+        mesh.delete(gl);
+    }
+
+    gl.uniform1i(
+        gl.get_uniform_location(program, "biomesEnabled").as_ref(),
+        1,
+    );
+}
+
+fn draw_mesh(gl: &WebGl2RenderingContext, program: &WebGlProgram, mesh: &Mesh) {
+    gl.bind_vertex_array(Some(&mesh.vao));
+    gl.uniform1i(
+        gl.get_uniform_location(program, "compact").as_ref(),
+        mesh.geometry.attributes.get("height").is_some() as i32,
+    );
+    gl.uniform1i(
+        gl.get_uniform_location(program, "solidColorEnabled")
+            .as_ref(),
+        mesh.color.is_some() as i32,
+    );
+    gl.uniform4fv_with_f32_array(
+        gl.get_uniform_location(program, "solidColor").as_ref(),
+        &mesh.color.unwrap_or_default(),
+    );
     gl.draw_elements_with_i32(
-        WebGl2RenderingContext::TRIANGLES,
-        geometry
+        mesh.mode,
+        mesh.geometry
             .triangles
             .len()
             .try_into()
@@ -318,11 +1020,43 @@ fn setup_event_listeners(
     gl: &WebGl2RenderingContext,
     pressed: &Rc<RefCell<PressedKeys>>,
     proj_mat: &Rc<RefCell<Matrix4<f32>>>,
+    scene: &Rc<Scene>,
 ) -> Result<(), JsValue> {
     let pressed_1 = Rc::clone(pressed);
+    let scene_1 = Rc::clone(scene);
     let onkeydown = Closure::<dyn FnMut(KeyboardEvent)>::new(move |e: KeyboardEvent| {
         let mut pressed = pressed_1.borrow_mut();
         match e.key_code() {
+            79 => scene_1.overlay.set(match scene_1.overlay.get() {
+                None => Some(0),
+                Some(k) if k + 1 < scene_1.overlays.len() => Some(k + 1),
+                Some(_) => None,
+            }),
+            67 => scene_1
+                .contours_visible
+                .set(!scene_1.contours_visible.get()),
+            80 => scene_1.path_visible.set(!scene_1.path_visible.get()),
+            75 => scene_1.lakes_visible.set(!scene_1.lakes_visible.get()),
+            69 => {
+                scene_1.edit.set(match scene_1.edit.get() {
+                    None => Some(0),
+                    Some(k) if k + 1 < scene_1.edits.len() => Some(k + 1),
+                    Some(_) => None,
+                });
+                // Edits replace the full mesh only.
+                if scene_1.edit.get().is_some() {
+                    scene_1.terrain_mode.set(TerrainMode::Full);
+                }
+            }
+            76 => scene_1.terrain_mode.set(match scene_1.terrain_mode.get() {
+                TerrainMode::Full => TerrainMode::Quadtree,
+                TerrainMode::Quadtree => TerrainMode::Geomipmap,
+                TerrainMode::Geomipmap => TerrainMode::Infinite,
+                TerrainMode::Infinite => TerrainMode::Full,
+            }),
+            73 => scene_1
+                .stats_panel
+                .set_hidden(!scene_1.stats_panel.hidden()),
             87 => pressed.w = true,
             83 => pressed.s = true,
             65 => pressed.a = true,
@@ -357,7 +1091,7 @@ fn setup_event_listeners(
 
     let gl_1 = gl.clone();
     let proj_mat_1 = Rc::clone(proj_mat);
-    reset_aspect_ratio(&gl, &proj_mat_1)?;
+    reset_aspect_ratio(gl, &proj_mat_1)?;
 
     let onresize = Closure::<dyn FnMut()>::new(move || {
         reset_aspect_ratio(&gl_1, &proj_mat_1).expect("failed to reset aspect ratio");
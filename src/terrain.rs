@@ -1,17 +1,29 @@
-use nalgebra::Vector3;
-use std::f32::consts::PI;
+use nalgebra::{Vector2, Vector3};
+use std::{cmp::Ordering, f32::consts::PI};
 
 use crate::utils::{
-    geometry::compute_normals,
-    web::random_f32,
+    geometry::{add_tangents_mut, compute_normals, octahedral_encode, NormalWeighting},
+    random::Rng,
     webgl::{Geometry, VertexAttrInfo, VertexAttrs},
 };
 
 const BOX_SIZE: f32 = 500.0;
 const R: f32 = BOX_SIZE / 10.0;
-const RESOLUTION: i32 = 100;
+pub const RESOLUTION: i32 = 100;
 const SLICES: i32 = 100;
 const STEP: f32 = BOX_SIZE / (RESOLUTION as f32);
+/// Vertices after which a tileable heightfield repeats, its last row and
+/// column being copies of the first ones.
+const PERIOD: i32 = RESOLUTION - 1;
+/// Side of the torus a tileable heightfield covers.
+const PERIOD_SIZE: f32 = STEP * PERIOD as f32;
+/// Passes of `spheroidal_weather_mut`, each smoothing one vertex further.
+const WEATHER_ITERATIONS: usize = 5;
+/// Direction towards the light, the `light` uniform of
+/// `terrain_fragment.glsl`.
+pub const LIGHT: Vector3<f32> = Vector3::new(1.0, -1.0, 1.0);
+/// Lifts lines drawn over the terrain to avoid z-fighting.
+const LINE_OFFSET: f32 = 0.3;
 
 macro_rules! ind {
     ($i:expr, $j:expr) => {
@@ -19,7 +31,264 @@ macro_rules! ind {
     };
 }
 
-pub fn generate_terrain() -> Geometry {
+pub mod analysis;
+pub mod biomes;
+pub mod chunks;
+pub mod climate;
+pub mod contours;
+pub mod dunes;
+pub mod geomipmap;
+pub mod lakes;
+pub mod lod;
+pub mod pathfinding;
+pub mod raycast;
+pub mod rivers;
+pub mod roads;
+pub mod snow;
+pub mod statistics;
+pub mod tin;
+pub mod visibility;
+
+/// Neighbour offsets in counter-clockwise order starting from +x, so that
+/// cardinal and diagonal neighbours alternate.
+const NEIGHBORS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+fn in_bounds(i: i32, j: i32) -> bool {
+    (0..RESOLUTION).contains(&i) && (0..RESOLUTION).contains(&j)
+}
+
+/// Reorders per-vertex values into a row-major image with +y up, matching
+/// the texture coordinates of `terrain_geometry`.
+pub fn to_image<T: Copy>(values: &[T]) -> Vec<T> {
+    let mut image = Vec::with_capacity(values.len());
+    for row in 0..RESOLUTION {
+        for col in 0..RESOLUTION {
+            image.push(values[ind!(col, RESOLUTION - 1 - row) as usize]);
+        }
+    }
+    image
+}
+
+/// Height of the terrain surface at `(x, y)`, interpolated over the same two
+/// triangles per cell as `terrain_geometry`, or `None` outside the grid.
+pub fn height_at(positions: &[Vector3<f32>], x: f32, y: f32) -> Option<f32> {
+    let uv = ((x + BOX_SIZE * 0.5) / STEP, (y + BOX_SIZE * 0.5) / STEP);
+    grid_height(RESOLUTION as usize, uv, |i, j| {
+        positions[i * RESOLUTION as usize + j].z
+    })
+}
+
+/// `height_at` on a `size * size` grid of heights `z(i, j)`, at `(u, v)`
+/// measured in cells from vertex `(0, 0)`.
+fn grid_height(size: usize, (u, v): (f32, f32), z: impl Fn(usize, usize) -> f32) -> Option<f32> {
+    let last = (size - 1) as f32;
+    if !(0.0..=last).contains(&u) || !(0.0..=last).contains(&v) {
+        return None;
+    }
+    let (i, j) = (u.min(last - 1.0) as usize, v.min(last - 1.0) as usize);
+    let (fu, fv) = (u - i as f32, v - j as f32);
+    let z = |di: usize, dj: usize| z(i + di, j + dj);
+    Some(if fu + fv <= 1.0 {
+        z(0, 0) + fu * (z(1, 0) - z(0, 0)) + fv * (z(0, 1) - z(0, 0))
+    } else {
+        z(1, 1) + (1.0 - fu) * (z(0, 1) - z(1, 1)) + (1.0 - fv) * (z(1, 0) - z(1, 1))
+    })
+}
+
+/// Index of the vertex closest to `(x, y)`, clamped to the grid.
+pub fn nearest_vertex(x: f32, y: f32) -> usize {
+    let cell = |c: f32| {
+        i32::clamp(
+            ((c + BOX_SIZE * 0.5) / STEP).round() as i32,
+            0,
+            RESOLUTION - 1,
+        )
+    };
+    ind!(cell(x), cell(y)) as usize
+}
+
+/// How the edges of the grid are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edges {
+    /// The grid ends there, neighbours beyond being clamped to it.
+    Clamp,
+    /// The grid is a torus, the last row and column standing for the first
+    /// ones.
+    Wrap,
+}
+
+/// Central-difference height gradient at vertex `(i, j)`, one-sided at the
+/// border.
+fn gradient(positions: &[Vector3<f32>], i: i32, j: i32) -> Vector2<f32> {
+    gradient_with(positions, i, j, Edges::Clamp)
+}
+
+fn gradient_with(positions: &[Vector3<f32>], i: i32, j: i32, edges: Edges) -> Vector2<f32> {
+    grid_gradient(RESOLUTION, STEP, (i, j), edges, |i, j| {
+        positions[ind!(i, j) as usize].z
+    })
+}
+
+/// Central-difference gradient at vertex `(i, j)` of a `size * size` grid of
+/// heights `z(i, j)`, `spacing` apart.
+fn grid_gradient(
+    size: i32,
+    spacing: f32,
+    (i, j): (i32, i32),
+    edges: Edges,
+    z: impl Fn(i32, i32) -> f32,
+) -> Vector2<f32> {
+    let wrap = |k: i32| match edges {
+        Edges::Clamp => k,
+        Edges::Wrap => k.rem_euclid(size - 1),
+    };
+    let z = |i: i32, j: i32| z(wrap(i), wrap(j));
+    let (i0, i1, j0, j1) = match edges {
+        Edges::Clamp => (
+            i32::max(0, i - 1),
+            i32::min(size - 1, i + 1),
+            i32::max(0, j - 1),
+            i32::min(size - 1, j + 1),
+        ),
+        Edges::Wrap => (i - 1, i + 1, j - 1, j + 1),
+    };
+    Vector2::new(
+        (z(i1, j) - z(i0, j)) / (spacing * (i1 - i0) as f32),
+        (z(i, j1) - z(i, j0)) / (spacing * (j1 - j0) as f32),
+    )
+}
+
+/// Unit normal of every vertex from the central-difference gradient of the
+/// heightfield.
+pub fn heightfield_normals(positions: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
+    normals_with(positions, Edges::Clamp)
+}
+
+/// `heightfield_normals` of a heightfield from
+/// `generate_tileable_heightfield`, taking the differences across the edges.
+pub fn tileable_normals(positions: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
+    normals_with(positions, Edges::Wrap)
+}
+
+fn normals_with(positions: &[Vector3<f32>], edges: Edges) -> Vec<Vector3<f32>> {
+    let mut normals = vec![Vector3::zeros(); positions.len()];
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            let g = gradient_with(positions, i, j, edges);
+            normals[ind!(i, j) as usize] = Vector3::new(-g.x, -g.y, 1.0).normalize();
+        }
+    }
+    normals
+}
+
+/// How `terrain_geometry` computes vertex normals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerrainNormals {
+    /// Weighted average of the normals of the triangles around each vertex.
+    Mesh(NormalWeighting),
+    /// `heightfield_normals`.
+    Analytic,
+    /// `tileable_normals`.
+    Tileable,
+}
+
+/// Square grid of heights of any size, laid out like `ind!` with `size` in
+/// place of `RESOLUTION`: `i` runs along x and `j` along y.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    pub size: usize,
+    /// Distance between neighbouring vertices.
+    pub spacing: f32,
+    /// Position of vertex `(0, 0)`.
+    pub origin: Vector2<f32>,
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Heights of a `RESOLUTION * RESOLUTION` heightfield, on the ideal grid
+    /// rather than where weathering pulled the border vertices in.
+    pub fn from_positions(positions: &[Vector3<f32>]) -> Heightmap {
+        Heightmap {
+            size: RESOLUTION as usize,
+            spacing: STEP,
+            origin: Vector2::repeat(-0.5 * BOX_SIZE),
+            heights: positions.iter().map(|v| v.z).collect(),
+        }
+    }
+
+    pub fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[i * self.size + j]
+    }
+
+    pub fn position(&self, i: usize, j: usize) -> Vector3<f32> {
+        Vector3::new(
+            self.origin.x + self.spacing * i as f32,
+            self.origin.y + self.spacing * j as f32,
+            self.height(i, j),
+        )
+    }
+
+    /// Height at a point, interpolated over the triangles of
+    /// `terrain_geometry`, or `None` outside the map.
+    pub fn height_at(&self, x: f32, y: f32) -> Option<f32> {
+        let uv = (
+            (x - self.origin.x) / self.spacing,
+            (y - self.origin.y) / self.spacing,
+        );
+        grid_height(self.size, uv, |i, j| self.height(i, j))
+    }
+
+    /// Central-difference normal, one-sided at the border, like
+    /// `heightfield_normals`.
+    pub fn normal(&self, i: usize, j: usize) -> Vector3<f32> {
+        let g = grid_gradient(
+            self.size as i32,
+            self.spacing,
+            (i as i32, j as i32),
+            Edges::Clamp,
+            |i, j| self.height(i as usize, j as usize),
+        );
+        Vector3::new(-g.x, -g.y, 1.0).normalize()
+    }
+
+    /// Texture coordinates of a point, mapping the whole map to the unit
+    /// square like `texcoord` does for the standard grid.
+    pub fn texcoord(&self, p: &Vector3<f32>) -> Vec<f32> {
+        let extent = self.spacing * self.size as f32;
+        vec![
+            (p.x - self.origin.x) / extent,
+            1.0 - (p.y - self.origin.y) / extent,
+        ]
+    }
+}
+
+/// Grid of `RESOLUTION * RESOLUTION` vertices laid out by `ind!`, faulted and
+/// weathered, ready to be post-processed before `terrain_geometry`. The same
+/// `rng` state gives the same terrain.
+pub fn generate_heightfield(rng: &mut Rng) -> Vec<Vector3<f32>> {
+    heightfield(Edges::Clamp, rng)
+}
+
+/// Like `generate_heightfield`, but on a torus so that the heights repeat
+/// every `RESOLUTION - 1` vertices, the last row and column matching the
+/// first ones: copies laid `(RESOLUTION - 1) * STEP` apart overlap on them
+/// without a seam. Faults run along lattice directions, closing on themselves, and
+/// weathering continues across the edges. Shade with
+/// `TerrainNormals::Tileable`.
+pub fn generate_tileable_heightfield(rng: &mut Rng) -> Vec<Vector3<f32>> {
+    heightfield(Edges::Wrap, rng)
+}
+
+fn heightfield(edges: Edges, rng: &mut Rng) -> Vec<Vector3<f32>> {
     let mut delta = BOX_SIZE / 10.0;
 
     let mut positions = Vec::<Vector3<f32>>::new();
@@ -36,11 +305,27 @@ pub fn generate_terrain() -> Geometry {
     for _ in 0..SLICES {
         delta *= 0.99;
         let p = Vector3::new(
-            random_f32() * BOX_SIZE - BOX_SIZE * 0.5,
-            random_f32() * BOX_SIZE - BOX_SIZE * 0.5,
+            rng.next_f32() * BOX_SIZE - BOX_SIZE * 0.5,
+            rng.next_f32() * BOX_SIZE - BOX_SIZE * 0.5,
             0.0,
         );
-        let theta = random_f32() * PI * 2.0;
+        if edges == Edges::Wrap {
+            let (normal, spacing) = lattice_normal(rng);
+            for i in 0..PERIOD {
+                for j in 0..PERIOD {
+                    let v = &mut positions[ind!(i, j) as usize];
+                    // The fault and its copies behind it each raise the
+                    // ground like the single fault below.
+                    let mut d = normal.dot(&(*v - p)).rem_euclid(spacing);
+                    while d < R * R {
+                        v.z += delta * (1.0 - d / (R * R)).powi(2);
+                        d += spacing;
+                    }
+                }
+            }
+            continue;
+        }
+        let theta = rng.next_f32() * PI * 2.0;
         let normal = Vector3::new(f32::cos(theta), f32::sin(theta), 0.0);
         for v in positions.iter_mut() {
             let dir = *v - p;
@@ -54,14 +339,105 @@ pub fn generate_terrain() -> Geometry {
         }
     }
 
+    if edges == Edges::Wrap {
+        copy_wrapped_edges_mut(&mut positions);
+    }
     let z_max = positions.iter().map(|v| v.z).reduce(f32::max).unwrap();
     let z_min = positions.iter().map(|v| v.z).reduce(f32::min).unwrap();
     for v in positions.iter_mut() {
         v.z = (v.z - z_min) / (z_max - z_min) * BOX_SIZE * 0.5;
     }
 
-    let mut triangles = Vec::<(u32, u32, u32)>::new();
+    spheroidal_weather_mut(&mut positions, edges);
+    positions
+}
+
+/// Normal of a random fault along a lattice direction `(a, b)`, which closes
+/// on itself after wrapping `a` times around x and `b` times around y, with
+/// the spacing of its parallel copies on the torus.
+fn lattice_normal(rng: &mut Rng) -> (Vector3<f32>, f32) {
+    loop {
+        let (a, b) = (rng.below(9) as i32 - 4, rng.below(9) as i32 - 4);
+        if gcd(a, b) == 1 {
+            let length = ((a * a + b * b) as f32).sqrt();
+            return (
+                Vector3::new(-b as f32, a as f32, 0.0) / length,
+                PERIOD_SIZE / length,
+            );
+        }
+    }
+}
 
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Mesh of a heightfield with positions, normals, texture coordinates and
+/// the tangents of `add_tangents_mut`.
+pub fn terrain_geometry(positions: &[Vector3<f32>], normals: TerrainNormals) -> Geometry {
+    let triangles = grid_triangles();
+    let normals = terrain_normals(positions, &triangles, normals);
+
+    let mut geometry = Geometry {
+        triangles: triangles
+            .iter()
+            .flat_map(|&s| vec![s.0, s.1, s.2])
+            .collect(),
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "normal",
+                3,
+                normals.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32("texCoord", 2, positions.iter().flat_map(texcoord).collect()),
+        ]),
+    };
+    add_tangents_mut(&mut geometry);
+    geometry
+}
+
+/// Same grid as `terrain_geometry` without tangents, in 12 rather than 48
+/// bytes per vertex: only the height is stored, with octahedral normals and
+/// 16-bit texture coordinates. The shader derives x and y from the vertex
+/// index, so vertices sit on the ideal grid: the border ones weathering
+/// pulled inwards, by up to half a step, move back out.
+pub fn compact_terrain_geometry(positions: &[Vector3<f32>], normals: TerrainNormals) -> Geometry {
+    let triangles = grid_triangles();
+    let normals = terrain_normals(positions, &triangles, normals);
+    let octahedral: Vec<f32> = normals
+        .iter()
+        .flat_map(|n| {
+            let e = octahedral_encode(n);
+            vec![e.x, e.y]
+        })
+        .collect();
+    let texcoords: Vec<f32> = positions.iter().flat_map(texcoord).collect();
+
+    Geometry {
+        triangles: triangles
+            .iter()
+            .flat_map(|&s| vec![s.0, s.1, s.2])
+            .collect(),
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32("height", 1, positions.iter().map(|p| p.z).collect()),
+            VertexAttrInfo::snorm16("octNormal", 2, &octahedral),
+            VertexAttrInfo::unorm16("texCoord", 2, &texcoords),
+        ]),
+    }
+}
+
+/// The two triangles of every cell of a `RESOLUTION * RESOLUTION` grid.
+pub fn grid_triangles() -> Vec<(u32, u32, u32)> {
+    let mut triangles = Vec::<(u32, u32, u32)>::new();
     for i in 0..RESOLUTION - 1 {
         for j in 0..RESOLUTION - 1 {
             triangles.push((
@@ -76,54 +452,85 @@ pub fn generate_terrain() -> Geometry {
             ));
         }
     }
+    triangles
+}
 
-    spheroidal_weather_mut(&mut positions);
+fn terrain_normals(
+    positions: &[Vector3<f32>],
+    triangles: &[(u32, u32, u32)],
+    normals: TerrainNormals,
+) -> Vec<Vector3<f32>> {
+    match normals {
+        TerrainNormals::Mesh(weighting) => compute_normals(positions, triangles, weighting),
+        TerrainNormals::Analytic => heightfield_normals(positions),
+        TerrainNormals::Tileable => tileable_normals(positions),
+    }
+}
 
-    let normals = compute_normals(&positions, &triangles);
+/// Polylines as line segments slightly above the terrain. The indices are
+/// pairs meant to be drawn with `LINES`.
+pub fn polyline_geometry(polylines: &[Vec<Vector3<f32>>]) -> Geometry {
+    let mut points = Vec::<Vector3<f32>>::new();
+    let mut lines = Vec::<u32>::new();
+    for line in polylines.iter().filter(|line| !line.is_empty()) {
+        let first = points.len() as u32;
+        points.extend(line.iter().map(|p| p + Vector3::new(0.0, 0.0, LINE_OFFSET)));
+        for k in first..points.len() as u32 - 1 {
+            lines.push(k);
+            lines.push(k + 1);
+        }
+    }
 
     Geometry {
-        triangles: triangles
-            .iter()
-            .flat_map(|&s| vec![s.0, s.1, s.2])
-            .collect(),
-        attributes: VertexAttrs {
-            position: VertexAttrInfo {
-                glsl_name: String::from("position"),
-                size: 3,
-                data: positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
-            },
-            normal: Some(VertexAttrInfo {
-                glsl_name: String::from("normal"),
-                size: 3,
-                data: normals.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
-            }),
-            texcoord: Some(VertexAttrInfo {
-                glsl_name: String::from("texCoord"),
-                size: 2,
-                data: positions
-                    .iter()
-                    .flat_map(|p| {
-                        vec![
-                            (p.x + BOX_SIZE * 0.5) / BOX_SIZE,
-                            (BOX_SIZE * 0.5 - p.y) / BOX_SIZE,
-                        ]
-                    })
-                    .collect(),
-            }),
-        },
-    }
-}
-
-fn spheroidal_weather_mut(positions: &mut Vec<Vector3<f32>>) {
-    for _ in 0..5 {
+        triangles: lines,
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                points.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "normal",
+                3,
+                points.iter().flat_map(|_| vec![0.0, 0.0, 1.0]).collect(),
+            ),
+            VertexAttrInfo::f32("texCoord", 2, points.iter().flat_map(texcoord).collect()),
+        ]),
+    }
+}
+
+fn texcoord(p: &Vector3<f32>) -> Vec<f32> {
+    vec![
+        (p.x + BOX_SIZE * 0.5) / BOX_SIZE,
+        (BOX_SIZE * 0.5 - p.y) / BOX_SIZE,
+    ]
+}
+
+fn spheroidal_weather_mut(positions: &mut [Vector3<f32>], edges: Edges) {
+    // Neighbour of vertex `(i, j)`, moved next to it when across an edge.
+    let neighbour = |positions: &[Vector3<f32>], i: i32, j: i32| match edges {
+        Edges::Clamp => {
+            positions[ind!(i.clamp(0, RESOLUTION - 1), j.clamp(0, RESOLUTION - 1)) as usize]
+        }
+        Edges::Wrap => {
+            let (wi, wj) = (i.rem_euclid(PERIOD), j.rem_euclid(PERIOD));
+            positions[ind!(wi, wj) as usize]
+                + Vector3::new(
+                    ((i - wi) / PERIOD) as f32 * PERIOD_SIZE,
+                    ((j - wj) / PERIOD) as f32 * PERIOD_SIZE,
+                    0.0,
+                )
+        }
+    };
+    for _ in 0..WEATHER_ITERATIONS {
         let mut average = vec![Vector3::<f32>::zeros(); positions.len()];
         for i in 0..RESOLUTION {
             for j in 0..RESOLUTION {
                 average[ind!(i, j) as usize] = 0.25
-                    * (positions[ind!(i32::max(0, i - 1), j) as usize]
-                        + positions[ind!(i32::min(RESOLUTION - 1, i + 1), j) as usize]
-                        + positions[ind!(i, i32::max(0, j - 1)) as usize]
-                        + positions[ind!(i, i32::min(RESOLUTION - 1, j + 1)) as usize]);
+                    * (neighbour(positions, i - 1, j)
+                        + neighbour(positions, i + 1, j)
+                        + neighbour(positions, i, j - 1)
+                        + neighbour(positions, i, j + 1));
             }
         }
         for i in 0..RESOLUTION {
@@ -133,5 +540,127 @@ fn spheroidal_weather_mut(positions: &mut Vec<Vector3<f32>>) {
                 *p = 0.5 * (*p + *a);
             }
         }
+        if edges == Edges::Wrap {
+            copy_wrapped_edges_mut(positions);
+        }
+    }
+}
+
+/// Makes the last row and column of a tileable heightfield copies of the
+/// first ones, a period further along.
+fn copy_wrapped_edges_mut(positions: &mut [Vector3<f32>]) {
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            if i == PERIOD || j == PERIOD {
+                let shift = Vector3::new(
+                    (i / PERIOD) as f32 * PERIOD_SIZE,
+                    (j / PERIOD) as f32 * PERIOD_SIZE,
+                    0.0,
+                );
+                positions[ind!(i, j) as usize] =
+                    positions[ind!(i % PERIOD, j % PERIOD) as usize] + shift;
+            }
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Min-heap entry ordered by its key, a height or a cost.
+#[derive(PartialEq)]
+struct Lowest(f32, usize);
+
+impl Eq for Lowest {}
+
+impl Ord for Lowest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+
+impl PartialOrd for Lowest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heightmap_matches_the_mesh_inside() {
+        let positions = generate_heightfield(&mut Rng::new(4));
+        let map = Heightmap::from_positions(&positions);
+        let geometry = terrain_geometry(&positions, TerrainNormals::Analytic);
+        let mesh = geometry
+            .attributes
+            .get("position")
+            .and_then(|a| a.data.as_f32())
+            .unwrap();
+        // Weathering only moves vertices near the border.
+        for i in 10..RESOLUTION as usize - 10 {
+            for j in 10..RESOLUTION as usize - 10 {
+                let k = 3 * (i * RESOLUTION as usize + j);
+                let v = Vector3::new(mesh[k], mesh[k + 1], mesh[k + 2]);
+                assert!((map.position(i, j) - v).norm() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn tileable_heights_wrap() {
+        let positions = generate_tileable_heightfield(&mut Rng::new(9));
+        let last = RESOLUTION - 1;
+        for k in 0..RESOLUTION {
+            let (first_row, last_row) = (
+                positions[ind!(0, k) as usize],
+                positions[ind!(last, k) as usize],
+            );
+            assert_eq!(last_row.z, first_row.z);
+            assert!(
+                (last_row.xy() - first_row.xy() - Vector2::new(PERIOD_SIZE, 0.0)).norm() < 1e-3
+            );
+            let (first_col, last_col) = (
+                positions[ind!(k, 0) as usize],
+                positions[ind!(k, last) as usize],
+            );
+            assert_eq!(last_col.z, first_col.z);
+            assert!(
+                (last_col.xy() - first_col.xy() - Vector2::new(0.0, PERIOD_SIZE)).norm() < 1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn tileable_normals_wrap() {
+        let positions = generate_tileable_heightfield(&mut Rng::new(9));
+        let normals = tileable_normals(&positions);
+        let z = |i: i32, j: i32| positions[ind!(i, j) as usize].z;
+        let last = RESOLUTION - 1;
+        // Differences across the edge, reaching past the copied row or
+        // column to the one before it.
+        let expected = |zx: f32, zy: f32| Vector3::new(-zx, -zy, 2.0 * STEP).normalize();
+        for k in 1..last {
+            let n = expected(z(1, k) - z(last - 1, k), z(0, k + 1) - z(0, k - 1));
+            assert!((normals[ind!(0, k) as usize] - n).norm() < 1e-6);
+            assert_eq!(
+                normals[ind!(last, k) as usize],
+                normals[ind!(0, k) as usize]
+            );
+            let n = expected(z(k + 1, 0) - z(k - 1, 0), z(k, 1) - z(k, last - 1));
+            assert!((normals[ind!(k, 0) as usize] - n).norm() < 1e-6);
+            assert_eq!(
+                normals[ind!(k, last) as usize],
+                normals[ind!(k, 0) as usize]
+            );
+        }
+        let corner = expected(z(1, 0) - z(last - 1, 0), z(0, 1) - z(0, last - 1));
+        for (i, j) in [(0, 0), (0, last), (last, 0), (last, last)] {
+            assert!((normals[ind!(i, j) as usize] - corner).norm() < 1e-6);
+        }
     }
 }
@@ -0,0 +1,133 @@
+use nalgebra::Vector3;
+
+use super::{gradient, to_image, RESOLUTION, STEP};
+use crate::utils::image::{color_ramp, encode_pgm, encode_ppm};
+
+/// Steepness of every vertex, in degrees.
+pub fn slope(positions: &[Vector3<f32>]) -> Vec<f32> {
+    per_vertex(|i, j| gradient(positions, i, j).norm().atan().to_degrees())
+}
+
+/// Compass direction every vertex faces, i.e. the downslope direction in
+/// degrees clockwise from +y. Flat vertices get -1.
+pub fn aspect(positions: &[Vector3<f32>]) -> Vec<f32> {
+    per_vertex(|i, j| {
+        let g = gradient(positions, i, j);
+        if g.norm() < f32::EPSILON {
+            -1.0
+        } else {
+            f32::atan2(-g.x, -g.y).to_degrees().rem_euclid(360.0)
+        }
+    })
+}
+
+/// Second derivative of height across the slope direction. Positive values
+/// are concave, where flow converges (hollows, valleys).
+pub fn plan_curvature(positions: &[Vector3<f32>]) -> Vec<f32> {
+    per_vertex(|i, j| {
+        let (zx, zy, zxx, zyy, zxy) = derivatives(positions, i, j);
+        let p = zx * zx + zy * zy;
+        if p < f32::EPSILON {
+            0.0
+        } else {
+            (zxx * zy * zy - 2.0 * zxy * zx * zy + zyy * zx * zx) / p
+        }
+    })
+}
+
+/// Second derivative of height along the slope direction. Positive values
+/// are concave, where flow decelerates (foot of slopes).
+pub fn profile_curvature(positions: &[Vector3<f32>]) -> Vec<f32> {
+    per_vertex(|i, j| {
+        let (zx, zy, zxx, zyy, zxy) = derivatives(positions, i, j);
+        let p = zx * zx + zy * zy;
+        if p < f32::EPSILON {
+            0.0
+        } else {
+            (zxx * zx * zx + 2.0 * zxy * zx * zy + zyy * zy * zy) / p
+        }
+    })
+}
+
+/// Standard deviation of the heights in the 3x3 neighbourhood of every
+/// vertex.
+pub fn roughness(positions: &[Vector3<f32>]) -> Vec<f32> {
+    per_vertex(|i, j| {
+        let heights: Vec<f32> = (i32::max(0, i - 1)..=i32::min(RESOLUTION - 1, i + 1))
+            .flat_map(|ni| {
+                (i32::max(0, j - 1)..=i32::min(RESOLUTION - 1, j + 1))
+                    .map(move |nj| positions[ind!(ni, nj) as usize].z)
+            })
+            .collect();
+        let mean = heights.iter().sum::<f32>() / heights.len() as f32;
+        let variance =
+            heights.iter().map(|z| (z - mean).powi(2)).sum::<f32>() / heights.len() as f32;
+        variance.sqrt()
+    })
+}
+
+/// Smallest and largest value of a map.
+pub fn value_range(values: &[f32]) -> (f32, f32) {
+    values
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+}
+
+/// Map as a PGM image with +y up, black at `range.0` and white at
+/// `range.1`.
+pub fn grayscale_image(values: &[f32], range: (f32, f32)) -> Vec<u8> {
+    let gray: Vec<u8> = to_image(values)
+        .into_iter()
+        .map(|v| (255.0 * normalize(v, range)).round() as u8)
+        .collect();
+    encode_pgm(RESOLUTION as usize, RESOLUTION as usize, &gray)
+}
+
+/// Map as row-major RGB bytes with +y up, colored with `color_ramp` over
+/// `range`.
+pub fn false_colors(values: &[f32], range: (f32, f32)) -> Vec<u8> {
+    to_image(values)
+        .into_iter()
+        .flat_map(|v| color_ramp(normalize(v, range)).to_vec())
+        .collect()
+}
+
+/// Map as a false-color PPM image.
+pub fn false_color_image(values: &[f32], range: (f32, f32)) -> Vec<u8> {
+    encode_ppm(
+        RESOLUTION as usize,
+        RESOLUTION as usize,
+        &false_colors(values, range),
+    )
+}
+
+fn normalize(v: f32, (lo, hi): (f32, f32)) -> f32 {
+    if hi > lo {
+        ((v - lo) / (hi - lo)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+fn per_vertex(f: impl Fn(i32, i32) -> f32) -> Vec<f32> {
+    let mut values = vec![0.0; (RESOLUTION * RESOLUTION) as usize];
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            values[ind!(i, j) as usize] = f(i, j);
+        }
+    }
+    values
+}
+
+/// First and second height derivatives `(zx, zy, zxx, zyy, zxy)` at an
+/// interior vertex, from the 3x3 neighbourhood (Zevenbergen & Thorne, 1987).
+/// Border vertices reuse their inner neighbour's second derivatives.
+fn derivatives(positions: &[Vector3<f32>], i: i32, j: i32) -> (f32, f32, f32, f32, f32) {
+    let g = gradient(positions, i, j);
+    let (ci, cj) = (i.clamp(1, RESOLUTION - 2), j.clamp(1, RESOLUTION - 2));
+    let z = |di: i32, dj: i32| positions[ind!(ci + di, cj + dj) as usize].z;
+    let zxx = (z(1, 0) - 2.0 * z(0, 0) + z(-1, 0)) / (STEP * STEP);
+    let zyy = (z(0, 1) - 2.0 * z(0, 0) + z(0, -1)) / (STEP * STEP);
+    let zxy = (z(1, 1) - z(1, -1) - z(-1, 1) + z(-1, -1)) / (4.0 * STEP * STEP);
+    (g.x, g.y, zxx, zyy, zxy)
+}
@@ -0,0 +1,153 @@
+use nalgebra::Vector3;
+
+use super::{climate::Climate, to_image, RESOLUTION};
+use crate::utils::image::encode_ppm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Biome {
+    Ocean,
+    Beach,
+    Desert,
+    Grassland,
+    Rainforest,
+    Taiga,
+    Tundra,
+    Alpine,
+}
+
+impl Biome {
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            Biome::Ocean => [30, 60, 140],
+            Biome::Beach => [220, 210, 160],
+            Biome::Desert => [210, 180, 110],
+            Biome::Grassland => [130, 170, 80],
+            Biome::Rainforest => [30, 110, 40],
+            Biome::Taiga => [60, 100, 70],
+            Biome::Tundra => [160, 160, 140],
+            Biome::Alpine => [240, 240, 245],
+        }
+    }
+}
+
+/// A cell of the Whittaker diagram: the biome of land whose temperature and
+/// relative moisture fall in the given half-open ranges.
+#[derive(Debug, Clone)]
+pub struct BiomeRule {
+    pub biome: Biome,
+    /// Temperature range, in °C.
+    pub temperature: (f32, f32),
+    /// Precipitation relative to the wettest vertex, between 0 and 1.
+    pub moisture: (f32, f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct BiomeTable {
+    /// Everything below is ocean.
+    pub sea_level: f32,
+    /// Land between the sea level and this height is beach.
+    pub beach_level: f32,
+    /// Everything above is alpine.
+    pub alpine_level: f32,
+    /// Checked in order; the first matching rule wins.
+    pub rules: Vec<BiomeRule>,
+    /// Biome of land no rule matches.
+    pub fallback: Biome,
+}
+
+impl Default for BiomeTable {
+    fn default() -> BiomeTable {
+        BiomeTable {
+            sea_level: 10.0,
+            beach_level: 15.0,
+            alpine_level: 200.0,
+            rules: vec![
+                BiomeRule {
+                    biome: Biome::Tundra,
+                    temperature: (f32::MIN, -5.0),
+                    moisture: (0.0, f32::MAX),
+                },
+                BiomeRule {
+                    biome: Biome::Taiga,
+                    temperature: (-5.0, 5.0),
+                    moisture: (0.0, f32::MAX),
+                },
+                BiomeRule {
+                    biome: Biome::Desert,
+                    temperature: (5.0, f32::MAX),
+                    moisture: (0.0, 0.1),
+                },
+                BiomeRule {
+                    biome: Biome::Rainforest,
+                    temperature: (18.0, f32::MAX),
+                    moisture: (0.4, f32::MAX),
+                },
+            ],
+            fallback: Biome::Grassland,
+        }
+    }
+}
+
+impl BiomeTable {
+    pub fn classify(&self, height: f32, temperature: f32, moisture: f32) -> Biome {
+        if height < self.sea_level {
+            return Biome::Ocean;
+        }
+        if height < self.beach_level {
+            return Biome::Beach;
+        }
+        if height >= self.alpine_level {
+            return Biome::Alpine;
+        }
+        self.rules
+            .iter()
+            .find(|rule| {
+                (rule.temperature.0..rule.temperature.1).contains(&temperature)
+                    && (rule.moisture.0..rule.moisture.1).contains(&moisture)
+            })
+            .map_or(self.fallback, |rule| rule.biome)
+    }
+}
+
+/// Biome of every vertex, from its height and climate.
+pub fn classify_biomes(
+    positions: &[Vector3<f32>],
+    climate: &Climate,
+    table: &BiomeTable,
+) -> Vec<Biome> {
+    let wettest = climate
+        .precipitation
+        .iter()
+        .cloned()
+        .fold(f32::EPSILON, f32::max);
+    positions
+        .iter()
+        .enumerate()
+        .map(|(k, v)| {
+            table.classify(
+                v.z,
+                climate.temperature[k],
+                climate.precipitation[k] / wettest,
+            )
+        })
+        .collect()
+}
+
+/// Biome colors as row-major RGB bytes with +y up, laid out like the
+/// terrain texture.
+pub fn biome_colors(biomes: &[Biome]) -> Vec<u8> {
+    to_image(biomes)
+        .into_iter()
+        .flat_map(|b| b.color().to_vec())
+        .collect()
+}
+
+/// Colored preview of the biome map as a PPM image.
+pub fn biome_preview(biomes: &[Biome]) -> Vec<u8> {
+    encode_ppm(
+        RESOLUTION as usize,
+        RESOLUTION as usize,
+        &biome_colors(biomes),
+    )
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use nalgebra::{Vector2, Vector3};
+
+use super::{texcoord, Heightmap, BOX_SIZE, SLICES, STEP, WEATHER_ITERATIONS};
+use crate::utils::random::Rng;
+use crate::utils::webgl::{Geometry, VertexAttrInfo, VertexAttrs};
+
+/// Cells along each side of a chunk.
+pub const CHUNK_CELLS: usize = 64;
+/// Side of the squares faults are scattered over, each getting as many as
+/// `generate_heightfield` puts in its box.
+const FAULT_CELL: f32 = BOX_SIZE;
+/// Half the length of a fault, along which its offset tapers off.
+const FAULT_HALF_LENGTH: f32 = 0.5 * BOX_SIZE;
+/// Distance from a fault over which its raised side falls back.
+const FAULT_WIDTH: f32 = 0.5 * BOX_SIZE;
+/// Scales summed fault offsets to heights like those of
+/// `generate_heightfield`.
+const HEIGHT_SCALE: f32 = 0.4;
+/// Vertices generated beyond each side of a chunk, so that weathering and
+/// normals at its border see the same neighbours as in the chunk next to it.
+const APRON: usize = WEATHER_ITERATIONS + 1;
+
+/// Chunk indices along x and y, chunk `(0, 0)` starting at the origin.
+pub type ChunkCoord = (i32, i32);
+
+#[derive(Debug)]
+pub struct Chunk {
+    /// Heights of the chunk's `CHUNK_CELLS + 1` vertices per side, sharing
+    /// the border ones with its neighbours.
+    pub heightmap: Heightmap,
+    pub geometry: Geometry,
+}
+
+pub fn chunk_at(x: f32, y: f32) -> ChunkCoord {
+    let extent = CHUNK_CELLS as f32 * STEP;
+    ((x / extent).floor() as i32, (y / extent).floor() as i32)
+}
+
+/// Chunks within `radius` chunks of `center`, nearest first.
+pub fn chunks_around(center: ChunkCoord, radius: i32) -> Vec<ChunkCoord> {
+    let mut coords: Vec<ChunkCoord> = (-radius..=radius)
+        .flat_map(|di| (-radius..=radius).map(move |dj| (center.0 + di, center.1 + dj)))
+        .collect();
+    coords.sort_by_key(|&(ci, cj)| (ci - center.0).pow(2) + (cj - center.1).pow(2));
+    coords
+}
+
+/// Generates a chunk of an unbounded faulted and weathered terrain. Every
+/// height depends only on `seed` and the vertex's world position, so chunks
+/// can be generated in any order and match their neighbours exactly.
+pub fn generate_chunk(seed: u64, coord: ChunkCoord) -> Chunk {
+    let n = CHUNK_CELLS + 1 + 2 * APRON;
+    let first = (
+        coord.0 as i64 * CHUNK_CELLS as i64 - APRON as i64,
+        coord.1 as i64 * CHUNK_CELLS as i64 - APRON as i64,
+    );
+    let origin = Vector2::new(STEP * first.0 as f32, STEP * first.1 as f32);
+    let extent = STEP * (n - 1) as f32;
+    let reach = FAULT_HALF_LENGTH.hypot(FAULT_WIDTH);
+    let cells = |lo: f32| {
+        ((lo - reach) / FAULT_CELL).floor() as i32
+            ..=((lo + extent + reach) / FAULT_CELL).floor() as i32
+    };
+    // Every chunk adds up the faults in the same order, so that shared
+    // vertices come out bit for bit the same.
+    let faults: Vec<Fault> = cells(origin.x)
+        .flat_map(|ci| cells(origin.y).map(move |cj| (ci, cj)))
+        .flat_map(|cell| faults(seed, cell))
+        .collect();
+
+    let mut apron = Heightmap {
+        size: n,
+        spacing: STEP,
+        origin,
+        heights: vec![0.0; n * n],
+    };
+    // Faults go in the outer loop, visiting only the vertices they reach.
+    for fault in faults.iter() {
+        let (lo, hi) = fault.bounds();
+        let span = |lo: f32, hi: f32, origin: f32| {
+            let first = ((lo - origin) / STEP).ceil().max(0.0) as usize;
+            let last = ((hi - origin) / STEP).floor().min((n - 1) as f32);
+            first..(last + 1.0).max(0.0) as usize
+        };
+        for i in span(lo.x, hi.x, origin.x) {
+            for j in span(lo.y, hi.y, origin.y) {
+                let p = Vector2::new(
+                    STEP * (first.0 + i as i64) as f32,
+                    STEP * (first.1 + j as i64) as f32,
+                );
+                apron.heights[i * n + j] += HEIGHT_SCALE * fault.offset_at(&p);
+            }
+        }
+    }
+    weather_mut(&mut apron);
+
+    let size = CHUNK_CELLS + 1;
+    let mut heightmap = Heightmap {
+        size,
+        spacing: STEP,
+        origin: apron.position(APRON, APRON).xy(),
+        heights: Vec::with_capacity(size * size),
+    };
+    let mut normals = Vec::with_capacity(size * size);
+    for i in APRON..APRON + size {
+        for j in APRON..APRON + size {
+            heightmap.heights.push(apron.height(i, j));
+            normals.push(apron.normal(i, j));
+        }
+    }
+    let positions: Vec<Vector3<f32>> = (0..size)
+        .flat_map(|i| (0..size).map(move |j| (i, j)))
+        .map(|(i, j)| heightmap.position(i, j))
+        .collect();
+
+    let at = |i: usize, j: usize| (i * size + j) as u32;
+    let mut triangles = vec![];
+    for i in 0..size - 1 {
+        for j in 0..size - 1 {
+            triangles.extend_from_slice(&[at(i, j), at(i + 1, j), at(i, j + 1)]);
+            triangles.extend_from_slice(&[at(i, j + 1), at(i + 1, j), at(i + 1, j + 1)]);
+        }
+    }
+    let geometry = Geometry {
+        triangles,
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "normal",
+                3,
+                normals.iter().flat_map(|n| vec![n.x, n.y, n.z]).collect(),
+            ),
+            // Continues the mapping of the standard grid, for a texture
+            // that repeats.
+            VertexAttrInfo::f32("texCoord", 2, positions.iter().flat_map(texcoord).collect()),
+        ]),
+    };
+    Chunk {
+        heightmap,
+        geometry,
+    }
+}
+
+/// Fault of bounded extent: the ground on its positive side is raised by
+/// `offset`, falling back with the distance from it and tapering off
+/// towards its ends.
+struct Fault {
+    center: Vector2<f32>,
+    normal: Vector2<f32>,
+    offset: f32,
+}
+
+impl Fault {
+    /// Corners of the box around the raised side.
+    fn bounds(&self) -> (Vector2<f32>, Vector2<f32>) {
+        let along = Vector2::new(-self.normal.y, self.normal.x) * FAULT_HALF_LENGTH;
+        let across = self.normal * FAULT_WIDTH;
+        let corners = [
+            self.center + along,
+            self.center - along,
+            self.center + along + across,
+            self.center - along + across,
+        ];
+        let lo = corners.iter().fold(corners[0], |a, c| a.inf(c));
+        let hi = corners.iter().fold(corners[0], |a, c| a.sup(c));
+        (lo, hi)
+    }
+
+    fn offset_at(&self, p: &Vector2<f32>) -> f32 {
+        let d = p - self.center;
+        let across = self.normal.dot(&d) / FAULT_WIDTH;
+        let along = self.normal.perp(&d) / FAULT_HALF_LENGTH;
+        if across <= 0.0 || across >= 1.0 || along.abs() >= 1.0 {
+            return 0.0;
+        }
+        self.offset * (1.0 - across * across).powi(2) * (1.0 - along * along).powi(2)
+    }
+}
+
+/// Faults centred in one square of `FAULT_CELL`, with offsets shrinking
+/// like those of `generate_heightfield`.
+fn faults(seed: u64, (ci, cj): (i32, i32)) -> Vec<Fault> {
+    let mut rng = Rng::new(seed ^ ((ci as u32 as u64) << 32 | cj as u32 as u64));
+    (0..SLICES)
+        .map(|_| {
+            let center = Vector2::new(
+                (ci as f32 + rng.next_f32()) * FAULT_CELL,
+                (cj as f32 + rng.next_f32()) * FAULT_CELL,
+            );
+            let theta = rng.next_f32() * PI * 2.0;
+            let offset = BOX_SIZE / 10.0 * 0.99f32.powf(rng.next_f32() * SLICES as f32);
+            Fault {
+                center,
+                normal: Vector2::new(theta.cos(), theta.sin()),
+                offset,
+            }
+        })
+        .collect()
+}
+
+/// `spheroidal_weather_mut` on the heights of a heightmap, clamping at its
+/// border. Heights within `WEATHER_ITERATIONS` vertices of the border are
+/// off.
+fn weather_mut(map: &mut Heightmap) {
+    let n = map.size;
+    for _ in 0..WEATHER_ITERATIONS {
+        let z = map.heights.clone();
+        for i in 0..n {
+            for j in 0..n {
+                let average = 0.25
+                    * (z[i.saturating_sub(1) * n + j]
+                        + z[usize::min(n - 1, i + 1) * n + j]
+                        + z[i * n + j.saturating_sub(1)]
+                        + z[i * n + usize::min(n - 1, j + 1)]);
+                map.heights[i * n + j] = 0.5 * (z[i * n + j] + average);
+            }
+        }
+    }
+}
+
+/// Chunks uploaded so far, dropping the least recently used ones beyond a
+/// budget.
+#[derive(Debug)]
+pub struct ChunkCache<T> {
+    pub budget: usize,
+    clock: u64,
+    entries: HashMap<ChunkCoord, (u64, T)>,
+}
+
+impl<T> ChunkCache<T> {
+    pub fn new(budget: usize) -> ChunkCache<T> {
+        ChunkCache {
+            budget,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The chunk at `coord`, marking it as just used.
+    pub fn get(&mut self, coord: ChunkCoord) -> Option<&T> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(&coord).map(|entry| {
+            entry.0 = clock;
+            &entry.1
+        })
+    }
+
+    pub fn insert(&mut self, coord: ChunkCoord, chunk: T) {
+        self.clock += 1;
+        self.entries.insert(coord, (self.clock, chunk));
+    }
+
+    /// Removes the least recently used chunks until the budget is met,
+    /// returning them so that their resources can be released.
+    pub fn evict(&mut self) -> Vec<T> {
+        let mut evicted = vec![];
+        while self.entries.len() > self.budget {
+            let oldest = *self
+                .entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(coord, _)| coord)
+                .unwrap();
+            evicted.extend(self.entries.remove(&oldest).map(|(_, chunk)| chunk));
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 42;
+
+    fn normals(chunk: &Chunk) -> &[f32] {
+        chunk
+            .geometry
+            .attributes
+            .get("normal")
+            .and_then(|a| a.data.as_f32())
+            .unwrap()
+    }
+
+    #[test]
+    fn neighbours_share_their_border() {
+        let (left, right) = (generate_chunk(SEED, (0, 0)), generate_chunk(SEED, (1, 0)));
+        let (below, above) = (generate_chunk(SEED, (0, -1)), generate_chunk(SEED, (0, 0)));
+        let size = CHUNK_CELLS + 1;
+        let normal = |chunk: &Chunk, i: usize, j: usize| {
+            let k = 3 * (i * size + j);
+            normals(chunk)[k..k + 3].to_vec()
+        };
+        for k in 0..size {
+            // Along x, the last row of vertices of one chunk is the first of
+            // the next.
+            assert_eq!(
+                left.heightmap.position(CHUNK_CELLS, k),
+                right.heightmap.position(0, k)
+            );
+            assert_eq!(normal(&left, CHUNK_CELLS, k), normal(&right, 0, k));
+            assert_eq!(
+                below.heightmap.position(k, CHUNK_CELLS),
+                above.heightmap.position(k, 0)
+            );
+            assert_eq!(normal(&below, k, CHUNK_CELLS), normal(&above, k, 0));
+        }
+    }
+
+    #[test]
+    fn regenerates_the_same_chunk() {
+        let (a, b) = (generate_chunk(SEED, (-2, 3)), generate_chunk(SEED, (-2, 3)));
+        assert_eq!(a.heightmap.heights, b.heightmap.heights);
+        assert_eq!(normals(&a), normals(&b));
+        assert_ne!(
+            generate_chunk(SEED + 1, (-2, 3)).heightmap.heights,
+            a.heightmap.heights
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let mut cache = ChunkCache::new(2);
+        cache.insert((0, 0), "a");
+        cache.insert((1, 0), "b");
+        assert_eq!(cache.get((0, 0)), Some(&"a"));
+        cache.insert((2, 0), "c");
+        assert_eq!(cache.evict(), vec!["b"]);
+        assert_eq!(cache.get((1, 0)), None);
+        cache.get((2, 0));
+        cache.insert((3, 0), "d");
+        assert_eq!(cache.evict(), vec!["a"]);
+        assert!(cache.evict().is_empty());
+    }
+}
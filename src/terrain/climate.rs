@@ -0,0 +1,164 @@
+use nalgebra::Vector3;
+use std::f32::consts::FRAC_PI_4;
+
+use super::{in_bounds, NEIGHBORS, RESOLUTION, STEP};
+
+#[derive(Debug, Clone)]
+pub struct ClimateParams {
+    /// Latitude, in degrees, along the low-y and high-y edges of the grid.
+    pub latitude_range: (f32, f32),
+    /// Sea-level temperature at the equator, in °C.
+    pub equator_temperature: f32,
+    /// Sea-level temperature at the poles, in °C.
+    pub pole_temperature: f32,
+    /// Temperature drop per unit of altitude.
+    pub lapse_rate: f32,
+    /// Direction the prevailing wind blows towards, in radians from the x
+    /// axis.
+    pub wind_direction: f32,
+    /// Moisture carried by the wind as it enters the grid.
+    pub moisture: f32,
+    /// Fraction of the carried moisture raining out on flat ground.
+    pub background_rain: f32,
+    /// Fraction of the carried moisture raining out per unit of slope the
+    /// wind climbs.
+    pub orographic_rain: f32,
+    /// Fraction of the missing moisture regained per cell, from
+    /// evaporation.
+    pub recharge: f32,
+}
+
+impl Default for ClimateParams {
+    fn default() -> ClimateParams {
+        ClimateParams {
+            latitude_range: (30.0, 50.0),
+            equator_temperature: 30.0,
+            pole_temperature: -20.0,
+            lapse_rate: 0.05,
+            wind_direction: 0.0,
+            moisture: 2000.0,
+            background_rain: 0.01,
+            orographic_rain: 0.5,
+            recharge: 0.005,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Climate {
+    /// Mean temperature of every vertex, in °C.
+    pub temperature: Vec<f32>,
+    /// Precipitation of every vertex, in the same units as
+    /// `ClimateParams::moisture`.
+    pub precipitation: Vec<f32>,
+}
+
+pub fn compute_climate(positions: &[Vector3<f32>], params: &ClimateParams) -> Climate {
+    Climate {
+        temperature: temperature(positions, params),
+        precipitation: precipitation(positions, params),
+    }
+}
+
+/// Sea-level temperature from the latitude band, cooled with altitude.
+pub fn temperature(positions: &[Vector3<f32>], params: &ClimateParams) -> Vec<f32> {
+    let (south, north) = params.latitude_range;
+    let mut temperature = vec![0.0; positions.len()];
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            let k = ind!(i, j) as usize;
+            let latitude = south + (north - south) * j as f32 / (RESOLUTION - 1) as f32;
+            let sea_level = params.equator_temperature
+                - (params.equator_temperature - params.pole_temperature) * latitude.abs() / 90.0;
+            temperature[k] = sea_level - params.lapse_rate * positions[k].z;
+        }
+    }
+    temperature
+}
+
+/// Carries moisture across the grid with the prevailing wind. Air climbing
+/// a slope rains out (orographic lift), so the leeward side of ridges only
+/// gets what is left (rain shadow).
+pub fn precipitation(positions: &[Vector3<f32>], params: &ClimateParams) -> Vec<f32> {
+    let wind = Vector3::new(
+        f32::cos(params.wind_direction),
+        f32::sin(params.wind_direction),
+        0.0,
+    );
+
+    // The upwind direction falls between two adjacent neighbours, both of
+    // which are visited before the current vertex in wind order.
+    let upwind = (params.wind_direction + std::f32::consts::PI).rem_euclid(8.0 * FRAC_PI_4);
+    let sector = (upwind / FRAC_PI_4).floor() as usize % 8;
+    let t = upwind / FRAC_PI_4 - (upwind / FRAC_PI_4).floor();
+    let sources = [
+        (NEIGHBORS[sector], 1.0 - t),
+        (NEIGHBORS[(sector + 1) % 8], t),
+    ];
+
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|&a, &b| positions[a].dot(&wind).total_cmp(&positions[b].dot(&wind)));
+
+    let mut carried = vec![0.0; positions.len()];
+    let mut precipitation = vec![0.0; positions.len()];
+    for k in order {
+        let (i, j) = (k as i32 / RESOLUTION, k as i32 % RESOLUTION);
+        let mut moisture = 0.0;
+        let mut lift = 0.0;
+        for &((di, dj), w) in sources.iter() {
+            let (ui, uj) = (i + di, j + dj);
+            let (m, z) = if in_bounds(ui, uj) {
+                let u = ind!(ui, uj) as usize;
+                (carried[u], positions[u].z)
+            } else {
+                (params.moisture, positions[k].z)
+            };
+            moisture += w * m;
+            lift += w * (positions[k].z - z) / (STEP * ((di * di + dj * dj) as f32).sqrt());
+        }
+        let rate = params.background_rain + params.orographic_rain * lift.max(0.0);
+        let rain = moisture * rate.min(1.0);
+        precipitation[k] = rain;
+        carried[k] = moisture - rain + params.recharge * (params.moisture - moisture + rain);
+    }
+    precipitation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(z: impl Fn(f32) -> f32) -> Vec<Vector3<f32>> {
+        let mut positions = vec![];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                let (x, y) = (STEP * i as f32 - 250.0, STEP * j as f32 - 250.0);
+                positions.push(Vector3::new(x, y, z(x)));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn cools_with_altitude() {
+        let params = ClimateParams::default();
+        let low = temperature(&grid(|_| 0.0), &params);
+        let high = temperature(&grid(|_| 100.0), &params);
+        for (l, h) in low.iter().zip(high.iter()) {
+            assert!((l - h - 100.0 * params.lapse_rate).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn ridges_cast_a_rain_shadow() {
+        // Wind towards +x over a ridge along x = 0.
+        let params = ClimateParams::default();
+        let positions = grid(|x| 20.0 * f32::max(0.0, 1.0 - x.abs() / 100.0));
+        let rain = precipitation(&positions, &params);
+        let at = |i: i32| rain[ind!(i, RESOLUTION / 2) as usize];
+        let (upwind, windward, leeward) = (at(10), at(45), at(55));
+        assert!(windward > upwind);
+        assert!(leeward < windward);
+        assert!(at(70) < upwind);
+    }
+}
@@ -0,0 +1,251 @@
+use nalgebra::Vector3;
+use std::collections::HashMap;
+
+use super::{polyline_geometry, BOX_SIZE, RESOLUTION};
+use crate::utils::webgl::Geometry;
+
+#[derive(Debug)]
+pub struct Contour {
+    pub level: f32,
+    /// Open polylines end on the grid border; closed ones repeat their first
+    /// point at the end.
+    pub polylines: Vec<Vec<Vector3<f32>>>,
+}
+
+/// Grid edge from vertex `(i, j)` to `(i + 1, j)` (`false`) or `(i, j + 1)`
+/// (`true`).
+type Edge = (i32, i32, bool);
+
+/// Most levels `extract_contours` traces, each one a pass over the grid.
+pub const MAX_CONTOUR_LEVELS: u64 = (RESOLUTION * RESOLUTION) as u64;
+
+/// Isolines at every multiple of `interval` within the height range, or
+/// `None` unless `interval` is positive and finite and gives at most
+/// `MAX_CONTOUR_LEVELS` levels.
+pub fn extract_contours(positions: &[Vector3<f32>], interval: f32) -> Option<Vec<Contour>> {
+    if !(interval > 0.0 && interval.is_finite()) {
+        return None;
+    }
+    let z_min = positions.iter().map(|v| v.z).fold(f32::MAX, f32::min);
+    let z_max = positions.iter().map(|v| v.z).fold(f32::MIN, f32::max);
+    // Levels are counted rather than summed, so that an interval too small
+    // to change the level can't stall the loop.
+    let first = (z_min / interval).floor();
+    let levels = (z_max / interval).floor() - first + 1.0;
+    if !levels.is_finite() || levels > MAX_CONTOUR_LEVELS as f32 {
+        return None;
+    }
+    let mut contours = vec![];
+    for k in 0..levels.max(0.0) as u64 {
+        let level = (first + k as f32) * interval;
+        let polylines = contour_lines(positions, level);
+        if !polylines.is_empty() {
+            contours.push(Contour { level, polylines });
+        }
+    }
+    Some(contours)
+}
+
+/// Marching squares over every grid cell, with the segments joined into
+/// polylines.
+pub fn contour_lines(positions: &[Vector3<f32>], level: f32) -> Vec<Vec<Vector3<f32>>> {
+    let above = |i: i32, j: i32| positions[ind!(i, j) as usize].z >= level;
+    let mut segments = Vec::<(Edge, Edge)>::new();
+    for i in 0..RESOLUTION - 1 {
+        for j in 0..RESOLUTION - 1 {
+            // Corners counter-clockwise from (i, j), and the edges between
+            // consecutive corners.
+            let case = above(i, j) as u8
+                | (above(i + 1, j) as u8) << 1
+                | (above(i + 1, j + 1) as u8) << 2
+                | (above(i, j + 1) as u8) << 3;
+            let (bottom, right, top, left) = (
+                (i, j, false),
+                (i + 1, j, true),
+                (i, j + 1, false),
+                (i, j, true),
+            );
+            let center_above = 0.25
+                * [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)]
+                    .iter()
+                    .map(|&(ci, cj)| positions[ind!(ci, cj) as usize].z)
+                    .sum::<f32>()
+                >= level;
+            match case {
+                0 | 15 => {}
+                1 | 14 => segments.push((left, bottom)),
+                2 | 13 => segments.push((bottom, right)),
+                3 | 12 => segments.push((left, right)),
+                4 | 11 => segments.push((right, top)),
+                6 | 9 => segments.push((bottom, top)),
+                7 | 8 => segments.push((left, top)),
+                // Saddles are resolved by the average of the four corners.
+                5 if center_above => {
+                    segments.push((left, top));
+                    segments.push((bottom, right));
+                }
+                5 => {
+                    segments.push((left, bottom));
+                    segments.push((right, top));
+                }
+                10 if center_above => {
+                    segments.push((left, bottom));
+                    segments.push((right, top));
+                }
+                _ => {
+                    segments.push((left, top));
+                    segments.push((bottom, right));
+                }
+            }
+        }
+    }
+
+    let point = |(i, j, vertical): Edge| {
+        let a = positions[ind!(i, j) as usize];
+        let b = if vertical {
+            positions[ind!(i, j + 1) as usize]
+        } else {
+            positions[ind!(i + 1, j) as usize]
+        };
+        let t = (level - a.z) / (b.z - a.z);
+        Vector3::new(a.x + t * (b.x - a.x), a.y + t * (b.y - a.y), level)
+    };
+
+    let mut by_edge = HashMap::<Edge, Vec<usize>>::new();
+    for (s, &(a, b)) in segments.iter().enumerate() {
+        by_edge.entry(a).or_default().push(s);
+        by_edge.entry(b).or_default().push(s);
+    }
+
+    // Walk open lines from the border first so that they come out whole,
+    // then whatever is left forms closed loops.
+    let mut starts: Vec<(usize, Edge)> = vec![];
+    for (s, &(a, b)) in segments.iter().enumerate() {
+        for &e in [a, b].iter() {
+            if by_edge[&e].len() == 1 {
+                starts.push((s, e));
+            }
+        }
+    }
+    starts.extend(segments.iter().enumerate().map(|(s, &(a, _))| (s, a)));
+
+    let mut used = vec![false; segments.len()];
+    let mut polylines = vec![];
+    for (start, from) in starts {
+        if used[start] {
+            continue;
+        }
+        let mut line = vec![point(from)];
+        let (mut s, mut from) = (start, from);
+        loop {
+            used[s] = true;
+            let (a, b) = segments[s];
+            let to = if a == from { b } else { a };
+            line.push(point(to));
+            match by_edge[&to].iter().find(|&&n| !used[n]) {
+                Some(&n) => {
+                    s = n;
+                    from = to;
+                }
+                None => break,
+            }
+        }
+        polylines.push(line);
+    }
+    polylines
+}
+
+/// Contours as an SVG drawing in world units, with +y up.
+pub fn contours_svg(contours: &[Contour]) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+        -0.5 * BOX_SIZE,
+        -0.5 * BOX_SIZE,
+        BOX_SIZE,
+        BOX_SIZE
+    );
+    for contour in contours {
+        svg += &format!(
+            "  <g data-level=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"0.5\">\n",
+            contour.level
+        );
+        for line in contour.polylines.iter() {
+            let points: Vec<String> = line
+                .iter()
+                .map(|p| format!("{:.2},{:.2}", p.x, -p.y))
+                .collect();
+            svg += &format!("    <polyline points=\"{}\"/>\n", points.join(" "));
+        }
+        svg += "  </g>\n";
+    }
+    svg += "</svg>\n";
+    svg
+}
+
+/// Contours as a GeoJSON feature collection of line strings in world
+/// units, each with its `level` as a property.
+pub fn contours_geojson(contours: &[Contour]) -> String {
+    let features: Vec<String> = contours
+        .iter()
+        .flat_map(|contour| {
+            contour.polylines.iter().map(move |line| {
+                let coordinates: Vec<String> = line
+                    .iter()
+                    .map(|p| format!("[{:.2},{:.2}]", p.x, p.y))
+                    .collect();
+                format!(
+                    "{{\"type\":\"Feature\",\"properties\":{{\"level\":{}}},\
+                     \"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}}}}",
+                    contour.level,
+                    coordinates.join(",")
+                )
+            })
+        })
+        .collect();
+    format!(
+        "{{\"type\":\"FeatureCollection\",\"features\":[{}]}}",
+        features.join(",")
+    )
+}
+
+/// Contours as line segments slightly above the terrain.
+pub fn contour_geometry(contours: &[Contour]) -> Geometry {
+    let polylines: Vec<Vec<Vector3<f32>>> = contours
+        .iter()
+        .flat_map(|c| c.polylines.iter().cloned())
+        .collect();
+    polyline_geometry(&polylines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slope() -> Vec<Vector3<f32>> {
+        let mut positions = vec![];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                positions.push(Vector3::new(5.0 * i as f32, 5.0 * j as f32, i as f32));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn traces_every_level_in_range() {
+        let contours = extract_contours(&slope(), 10.0).unwrap();
+        let levels: Vec<f32> = contours.iter().map(|c| c.level).collect();
+        assert_eq!(
+            levels,
+            vec![10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0, 90.0]
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_levels() {
+        assert!(extract_contours(&slope(), 0.0).is_none());
+        assert!(extract_contours(&slope(), f32::NAN).is_none());
+        assert!(extract_contours(&slope(), 1e-6).is_none());
+        assert!(extract_contours(&slope(), 1.0).is_some());
+    }
+}
@@ -0,0 +1,245 @@
+use nalgebra::Vector3;
+
+use super::{RESOLUTION, STEP};
+use crate::utils::random::Rng;
+
+// Werner's slab model: sand is a stack of slabs per cell, slabs hop downwind
+// (saltation) and slide down slopes steeper than the angle of repose.
+const SLAB_HEIGHT: f32 = 0.5;
+const SHADOW_ANGLE: f32 = 15.0 * std::f32::consts::PI / 180.0;
+const REPOSE_ANGLE: f32 = 30.0 * std::f32::consts::PI / 180.0;
+const SHADOW_REACH: i32 = RESOLUTION / 4;
+const P_DEPOSIT_SAND: f32 = 0.6;
+const P_DEPOSIT_BARE: f32 = 0.4;
+
+#[derive(Debug, Clone)]
+pub struct DuneParams {
+    /// Direction the wind blows towards, in radians from the x axis.
+    pub wind_direction: f32,
+    /// Saltation hop length, in cells.
+    pub wind_strength: f32,
+    /// Sand thickness initially spread evenly over the bedrock.
+    pub sand_depth: f32,
+    /// Number of sweeps; each sweep picks as many slabs as there are cells.
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+impl Default for DuneParams {
+    fn default() -> DuneParams {
+        DuneParams {
+            wind_direction: 0.0,
+            wind_strength: 5.0,
+            sand_depth: 3.0,
+            iterations: 100,
+            seed: 0,
+        }
+    }
+}
+
+/// Piles sand dunes on top of the bedrock and returns the sand thickness of
+/// every vertex.
+pub fn add_dunes_mut(positions: &mut [Vector3<f32>], params: &DuneParams) -> Vec<f32> {
+    let sand = simulate_dunes(positions, params);
+    for (v, s) in positions.iter_mut().zip(sand.iter()) {
+        v.z += s;
+    }
+    sand
+}
+
+/// Runs the saltation/avalanche simulation over the bedrock and returns the
+/// resulting sand thickness of every vertex.
+pub fn simulate_dunes(bedrock: &[Vector3<f32>], params: &DuneParams) -> Vec<f32> {
+    let mut sim = Simulation {
+        bedrock,
+        slabs: vec![(params.sand_depth / SLAB_HEIGHT).round() as u32; bedrock.len()],
+        wind: Vector3::new(
+            f32::cos(params.wind_direction),
+            f32::sin(params.wind_direction),
+            0.0,
+        ),
+    };
+    let mut rng = Rng::new(params.seed);
+
+    let hop = sim.wind * params.wind_strength.max(1.0);
+    let (di, dj) = (hop.x.round() as i32, hop.y.round() as i32);
+    let (di, dj) = if di == 0 && dj == 0 {
+        (hop.x.signum() as i32, 0)
+    } else {
+        (di, dj)
+    };
+
+    for _ in 0..params.iterations * bedrock.len() {
+        let (i, j) = (
+            rng.below(RESOLUTION as usize) as i32,
+            rng.below(RESOLUTION as usize) as i32,
+        );
+        if sim.slabs[ind!(i, j) as usize] == 0 || sim.in_shadow(i, j) {
+            continue;
+        }
+        sim.slabs[ind!(i, j) as usize] -= 1;
+        sim.fill_hollow(i, j);
+
+        // Sand blown off the downwind edge re-enters upwind, as in Werner's
+        // periodic domain.
+        let (mut i, mut j) = (i, j);
+        loop {
+            i = (i + di).rem_euclid(RESOLUTION);
+            j = (j + dj).rem_euclid(RESOLUTION);
+            let p = if sim.in_shadow(i, j) {
+                1.0
+            } else if sim.slabs[ind!(i, j) as usize] > 0 {
+                P_DEPOSIT_SAND
+            } else {
+                P_DEPOSIT_BARE
+            };
+            if rng.next_f32() < p {
+                sim.slabs[ind!(i, j) as usize] += 1;
+                sim.slide(i, j);
+                break;
+            }
+        }
+    }
+
+    sim.slabs.iter().map(|&n| n as f32 * SLAB_HEIGHT).collect()
+}
+
+struct Simulation<'a> {
+    bedrock: &'a [Vector3<f32>],
+    slabs: Vec<u32>,
+    wind: Vector3<f32>,
+}
+
+impl Simulation<'_> {
+    fn surface(&self, i: i32, j: i32) -> f32 {
+        let k = ind!(i, j) as usize;
+        self.bedrock[k].z + self.slabs[k] as f32 * SLAB_HEIGHT
+    }
+
+    /// Cells behind a crest, looking upwind, are sheltered from the wind.
+    /// The probe wraps around the edges like the saltating slabs do.
+    fn in_shadow(&self, i: i32, j: i32) -> bool {
+        let h = self.surface(i, j);
+        for k in 1..=SHADOW_REACH {
+            let ui = (i as f32 - k as f32 * self.wind.x).round() as i32;
+            let uj = (j as f32 - k as f32 * self.wind.y).round() as i32;
+            let (ui, uj) = (ui.rem_euclid(RESOLUTION), uj.rem_euclid(RESOLUTION));
+            if self.surface(ui, uj) - h > k as f32 * STEP * f32::tan(SHADOW_ANGLE) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Steepest neighbour accepted by `eligible` whose height difference to
+    /// the centre (`sign` selects downhill or uphill) exceeds the angle of
+    /// repose.
+    fn steepest(
+        &self,
+        i: i32,
+        j: i32,
+        sign: f32,
+        eligible: impl Fn(i32, i32) -> bool,
+    ) -> Option<(i32, i32)> {
+        let h = self.surface(i, j);
+        let mut best = None;
+        let mut best_excess = 0.0;
+        for ni in i32::max(0, i - 1)..=i32::min(RESOLUTION - 1, i + 1) {
+            for nj in i32::max(0, j - 1)..=i32::min(RESOLUTION - 1, j + 1) {
+                if (ni == i && nj == j) || !eligible(ni, nj) {
+                    continue;
+                }
+                let dist = STEP * (((ni - i).pow(2) + (nj - j).pow(2)) as f32).sqrt();
+                let excess = sign * (h - self.surface(ni, nj)) - dist * f32::tan(REPOSE_ANGLE);
+                if excess > best_excess {
+                    best_excess = excess;
+                    best = Some((ni, nj));
+                }
+            }
+        }
+        best
+    }
+
+    /// Avalanches a freshly deposited slab downhill until it comes to rest.
+    fn slide(&mut self, mut i: i32, mut j: i32) {
+        while self.slabs[ind!(i, j) as usize] > 0 {
+            match self.steepest(i, j, 1.0, |_, _| true) {
+                Some((ni, nj)) => {
+                    self.slabs[ind!(i, j) as usize] -= 1;
+                    self.slabs[ind!(ni, nj) as usize] += 1;
+                    i = ni;
+                    j = nj;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Lets sand from over-steepened uphill neighbours slide into a freshly
+    /// eroded cell, propagating the hollow upslope. Bare rock can't slide, so
+    /// only neighbours with sand are considered.
+    fn fill_hollow(&mut self, mut i: i32, mut j: i32) {
+        while let Some((ni, nj)) =
+            self.steepest(i, j, -1.0, |ni, nj| self.slabs[ind!(ni, nj) as usize] > 0)
+        {
+            self.slabs[ind!(ni, nj) as usize] -= 1;
+            self.slabs[ind!(i, j) as usize] += 1;
+            i = ni;
+            j = nj;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hollows_fill_from_sand_past_bare_cliffs() {
+        let mut bedrock = vec![Vector3::zeros(); (RESOLUTION * RESOLUTION) as usize];
+        // A bare cliff is the steepest uphill neighbour, but only the sand
+        // pile on the other side can slide.
+        bedrock[ind!(5, 6) as usize].z = 100.0;
+        let mut sim = Simulation {
+            bedrock: &bedrock,
+            slabs: vec![0; bedrock.len()],
+            wind: Vector3::x(),
+        };
+        sim.slabs[ind!(5, 4) as usize] = 40;
+        sim.fill_hollow(5, 5);
+        assert_eq!(sim.slabs[ind!(5, 5) as usize], 1);
+        assert_eq!(sim.slabs[ind!(5, 4) as usize], 39);
+    }
+
+    #[test]
+    fn crests_shelter_across_the_upwind_edge() {
+        let mut bedrock = vec![Vector3::zeros(); (RESOLUTION * RESOLUTION) as usize];
+        // A ridge on the last row shelters the first rows from the wind
+        // blowing towards +x, across the periodic edge.
+        for j in 0..RESOLUTION {
+            bedrock[ind!(RESOLUTION - 1, j) as usize].z = 50.0;
+        }
+        let sim = Simulation {
+            bedrock: &bedrock,
+            slabs: vec![0; bedrock.len()],
+            wind: Vector3::x(),
+        };
+        assert!(sim.in_shadow(0, 10));
+        assert!(sim.in_shadow(3, 10));
+        assert!(!sim.in_shadow(50, 10));
+    }
+
+    #[test]
+    fn conserves_sand() {
+        let bedrock = vec![Vector3::zeros(); (RESOLUTION * RESOLUTION) as usize];
+        let params = DuneParams {
+            iterations: 5,
+            ..DuneParams::default()
+        };
+        let sand = simulate_dunes(&bedrock, &params);
+        let total: f32 = sand.iter().sum();
+        let expected = params.sand_depth * bedrock.len() as f32;
+        assert!((total - expected).abs() < 1e-3 * expected);
+        assert!(sand.iter().any(|&s| s != params.sand_depth));
+    }
+}
@@ -0,0 +1,332 @@
+use nalgebra::{Vector2, Vector3};
+
+use super::Heightmap;
+use crate::utils::webgl::{Geometry, VertexAttrInfo, VertexAttrs};
+
+#[derive(Debug, Clone)]
+pub struct GeomipmapParams {
+    /// Cells along each side of the shared patch mesh.
+    pub patch_cells: usize,
+    /// Distance up to which full-resolution patches are used, in widths of
+    /// a full-resolution patch. Each coarser level reaches twice as far.
+    /// Neighbouring levels only meet without cracks if this is at least
+    /// `2 * sqrt(2) / morph_start`.
+    pub base_range: f32,
+    /// Fraction of its range after which a patch starts morphing into the
+    /// next coarser level.
+    pub morph_start: f32,
+}
+
+impl Default for GeomipmapParams {
+    fn default() -> GeomipmapParams {
+        GeomipmapParams {
+            patch_cells: 16,
+            base_range: 5.0,
+            morph_start: 0.7,
+        }
+    }
+}
+
+/// One draw of the shared patch mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Patch {
+    pub level: usize,
+    /// Heightmap vertex at the patch's corner.
+    pub i0: usize,
+    pub j0: usize,
+    /// Heightmap vertices per patch cell, `2^level`.
+    pub stride: usize,
+    /// Horizontal eye distances over which vertices morph from this level to
+    /// the next coarser one.
+    pub morph_range: (f32, f32),
+}
+
+/// Shared mesh of `patch_cells * patch_cells` cells with integer grid
+/// coordinates in `gridCoord`, triangulated like `terrain_geometry`.
+pub fn patch_geometry(patch_cells: usize) -> Geometry {
+    let n = patch_cells as u32 + 1;
+    let mut triangles = vec![];
+    for a in 0..n - 1 {
+        for b in 0..n - 1 {
+            triangles.extend_from_slice(&[a * n + b, (a + 1) * n + b, a * n + b + 1]);
+            triangles.extend_from_slice(&[a * n + b + 1, (a + 1) * n + b, (a + 1) * n + b + 1]);
+        }
+    }
+    Geometry {
+        triangles,
+        attributes: VertexAttrs::new(vec![VertexAttrInfo::f32(
+            "gridCoord",
+            2,
+            (0..n)
+                .flat_map(|a| (0..n).flat_map(move |b| vec![a as f32, b as f32]))
+                .collect(),
+        )]),
+    }
+}
+
+/// Coarsest level of a heightmap, whose single patch covers it all.
+fn root_level(map: &Heightmap, params: &GeomipmapParams) -> usize {
+    let mut level = 0;
+    while params.patch_cells << level < map.size - 1 {
+        level += 1;
+    }
+    level
+}
+
+/// Distance up to which each level is used, finest first.
+pub fn lod_ranges(map: &Heightmap, params: &GeomipmapParams) -> Vec<f32> {
+    (0..=root_level(map, params))
+        .map(|level| {
+            params.base_range * params.patch_cells as f32 * map.spacing * (1 << level) as f32
+        })
+        .collect()
+}
+
+/// Patches covering the heightmap, finer towards `eye` (CDLOD, Strugar
+/// 2009). A patch is split while it is within the range of the next finer
+/// level.
+pub fn select_patches(map: &Heightmap, eye: &Vector3<f32>, params: &GeomipmapParams) -> Vec<Patch> {
+    let ranges = lod_ranges(map, params);
+    let root = ranges.len() - 1;
+    let last = map.size - 1;
+    let mut patches = vec![];
+    let mut stack = vec![(root, 0, 0)];
+    while let Some((level, i0, j0)) = stack.pop() {
+        let cells = params.patch_cells << level;
+        let lo = map.position(i0, j0);
+        let hi = map.position(usize::min(i0 + cells, last), usize::min(j0 + cells, last));
+        // Ranges are horizontal like clipmap rings, so that the distance to
+        // the patch's footprint bounds that of all its vertices.
+        let nearest = Vector2::new(eye.x.clamp(lo.x, hi.x), eye.y.clamp(lo.y, hi.y));
+        if level > 0 && (eye.xy() - nearest).norm() < ranges[level - 1] {
+            let half = cells / 2;
+            for &(ci, cj) in [
+                (i0, j0),
+                (i0 + half, j0),
+                (i0, j0 + half),
+                (i0 + half, j0 + half),
+            ]
+            .iter()
+            {
+                if ci < last && cj < last {
+                    stack.push((level - 1, ci, cj));
+                }
+            }
+            continue;
+        }
+
+        let morph_range = if level == root {
+            (f32::MAX, f32::MAX)
+        } else {
+            let previous = if level == 0 { 0.0 } else { ranges[level - 1] };
+            (
+                previous + params.morph_start * (ranges[level] - previous),
+                ranges[level],
+            )
+        };
+        patches.push(Patch {
+            level,
+            i0,
+            j0,
+            stride: 1 << level,
+            morph_range,
+        });
+    }
+    patches
+}
+
+/// How far a vertex at horizontal `distance` from the eye has morphed into
+/// the next coarser level, from 0 to 1.
+pub fn morph_factor(distance: f32, (start, end): (f32, f32)) -> f32 {
+    if end > start {
+        ((distance - start) / (end - start)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Height of heightmap vertex `(i, j)` morphed by `k` towards the surface of
+/// the grid with twice the `stride`, i.e. the midpoint of the coarse edge it
+/// lies on. Same as `morphedHeight` in `terrain_vertex.glsl`.
+pub fn morphed_height(map: &Heightmap, i: usize, j: usize, stride: usize, k: f32) -> f32 {
+    let last = map.size - 1;
+    let z = |i: usize, j: usize| map.height(i.min(last), j.min(last));
+    let fine = z(i, j);
+    let (odd_i, odd_j) = ((i / stride) % 2 == 1, (j / stride) % 2 == 1);
+    let coarse = match (odd_i, odd_j) {
+        (false, false) => fine,
+        (true, false) => 0.5 * (z(i - stride, j) + z(i + stride, j)),
+        (false, true) => 0.5 * (z(i, j - stride) + z(i, j + stride)),
+        // The coarse cell's diagonal runs from (i + 1, j) to (i, j + 1).
+        (true, true) => 0.5 * (z(i + stride, j - stride) + z(i - stride, j + stride)),
+    };
+    // GLSL's `mix`, exact at either end.
+    fine * (1.0 - k) + coarse * k
+}
+
+/// World position of vertex `(a, b)` of the shared mesh drawn as `patch`,
+/// as computed by `terrain_vertex.glsl`.
+pub fn patch_vertex(
+    map: &Heightmap,
+    patch: &Patch,
+    a: usize,
+    b: usize,
+    eye: &Vector3<f32>,
+) -> Vector3<f32> {
+    let last = map.size - 1;
+    let i = usize::min(patch.i0 + a * patch.stride, last);
+    let j = usize::min(patch.j0 + b * patch.stride, last);
+    let fine = map.position(i, j);
+    let k = morph_factor((fine.xy() - eye.xy()).norm(), patch.morph_range);
+    Vector3::new(fine.x, fine.y, morphed_height(map, i, j, patch.stride, k))
+}
+
+/// The patches `select_patches` picks for `eye`, morphed as the shader would
+/// draw them, as a single geometry for inspection outside the viewer.
+pub fn geomipmap_geometry(
+    map: &Heightmap,
+    eye: &Vector3<f32>,
+    params: &GeomipmapParams,
+) -> Geometry {
+    let shared = patch_geometry(params.patch_cells);
+    let n = params.patch_cells + 1;
+    let mut triangles = vec![];
+    let mut positions = vec![];
+    for patch in select_patches(map, eye, params) {
+        let first = positions.len() as u32;
+        triangles.extend(shared.triangles.iter().map(|&v| first + v));
+        for a in 0..n {
+            for b in 0..n {
+                positions.push(patch_vertex(map, &patch, a, b, eye));
+            }
+        }
+    }
+    Geometry {
+        triangles,
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "texCoord",
+                2,
+                positions.iter().flat_map(|p| map.texcoord(p)).collect(),
+            ),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random::Rng;
+
+    fn heightmap(size: usize) -> Heightmap {
+        let mut rng = Rng::new(5);
+        Heightmap {
+            size,
+            spacing: 5.0,
+            origin: Vector2::new(-250.0, -250.0),
+            heights: (0..size * size).map(|_| 100.0 * rng.next_f32()).collect(),
+        }
+    }
+
+    #[test]
+    fn morphs_from_fine_to_coarse() {
+        let map = heightmap(33);
+        let z = |i, j| map.height(i, j);
+        for stride in [1, 2, 4] {
+            for (i, j) in [(stride, 2 * stride), (2 * stride, stride), (stride, stride)] {
+                assert_eq!(morphed_height(&map, i, j, stride, 0.0), z(i, j));
+                let coarse = match (i / stride % 2, j / stride % 2) {
+                    (1, 0) => 0.5 * (z(i - stride, j) + z(i + stride, j)),
+                    (0, 1) => 0.5 * (z(i, j - stride) + z(i, j + stride)),
+                    _ => 0.5 * (z(i + stride, j - stride) + z(i - stride, j + stride)),
+                };
+                assert_eq!(morphed_height(&map, i, j, stride, 1.0), coarse);
+            }
+            // Vertices of the coarser grid never move.
+            let (i, j) = (2 * stride, 4 * stride);
+            assert_eq!(morphed_height(&map, i, j, stride, 1.0), z(i, j));
+        }
+    }
+
+    #[test]
+    fn clamps_morph_factor() {
+        assert_eq!(morph_factor(10.0, (20.0, 30.0)), 0.0);
+        assert_eq!(morph_factor(25.0, (20.0, 30.0)), 0.5);
+        assert_eq!(morph_factor(40.0, (20.0, 30.0)), 1.0);
+        assert_eq!(morph_factor(40.0, (f32::MAX, f32::MAX)), 0.0);
+    }
+
+    /// Height of `patch`'s mesh at heightmap vertex `(i, j)` on its border.
+    fn border_height(
+        map: &Heightmap,
+        patch: &Patch,
+        (i, j): (usize, usize),
+        eye: &Vector3<f32>,
+    ) -> f32 {
+        let (a, b) = ((i - patch.i0) / patch.stride, (j - patch.j0) / patch.stride);
+        let p = patch_vertex(map, patch, a, b, eye);
+        let along = |q: Vector3<f32>| {
+            let t = (p.xy() - map.position(i, j).xy()).norm() / (p.xy() - q.xy()).norm();
+            p.z + t * (q.z - p.z)
+        };
+        if p.xy() == map.position(i, j).xy() {
+            p.z
+        } else if !(i - patch.i0).is_multiple_of(patch.stride) {
+            along(patch_vertex(map, patch, a + 1, b, eye))
+        } else {
+            along(patch_vertex(map, patch, a, b + 1, eye))
+        }
+    }
+
+    #[test]
+    fn neighbouring_levels_meet_without_cracks() {
+        let map = heightmap(100);
+        let params = GeomipmapParams {
+            patch_cells: 4,
+            ..GeomipmapParams::default()
+        };
+        let last = map.size - 1;
+        for eye in [
+            Vector3::new(-240.0, -240.0, 80.0),
+            Vector3::new(0.0, 30.0, 150.0),
+        ] {
+            let patches = select_patches(&map, &eye, &params);
+            let footprint = |p: &Patch| {
+                let cells = params.patch_cells * p.stride;
+                (
+                    p.i0..=usize::min(p.i0 + cells, last),
+                    p.j0..=usize::min(p.j0 + cells, last),
+                )
+            };
+            assert!(patches.iter().any(|p| p.level > 1));
+            let n = params.patch_cells;
+            let mut checked = 0;
+            for p in patches.iter() {
+                let border = (0..=n)
+                    .flat_map(|a| (0..=n).map(move |b| (a, b)))
+                    .filter(|&(a, b)| a == 0 || b == 0 || a == n || b == n);
+                for (a, b) in border {
+                    let v = patch_vertex(&map, p, a, b, &eye);
+                    let ij = (
+                        usize::min(p.i0 + a * p.stride, last),
+                        usize::min(p.j0 + b * p.stride, last),
+                    );
+                    for q in patches.iter().filter(|&q| q != p) {
+                        let (is, js) = footprint(q);
+                        if is.contains(&ij.0) && js.contains(&ij.1) {
+                            let z = border_height(&map, q, ij, &eye);
+                            assert!((v.z - z).abs() < 1e-3, "{:?} {:?}", p, q);
+                            checked += 1;
+                        }
+                    }
+                }
+            }
+            assert!(checked > 0);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use nalgebra::Vector3;
+use std::collections::BinaryHeap;
+
+use super::{in_bounds, texcoord, Lowest, NEIGHBORS, RESOLUTION, STEP};
+use crate::utils::webgl::{Geometry, VertexAttrInfo, VertexAttrs};
+
+/// Filled vertices shallower than this are not considered under water.
+const LAKE_MIN_DEPTH: f32 = 0.01;
+
+#[derive(Debug)]
+pub struct Lake {
+    /// Height of the flat water surface.
+    pub level: f32,
+    /// Vertices under water.
+    pub cells: Vec<usize>,
+    /// Shore vertex through which the lake spills over.
+    pub outflow: usize,
+}
+
+#[derive(Debug)]
+pub struct LakeMap {
+    /// Heights with every depression filled up to its spill level.
+    pub filled: Vec<f32>,
+    /// Index into `lakes` of every vertex under water.
+    pub lake_ids: Vec<Option<usize>>,
+    pub lakes: Vec<Lake>,
+}
+
+/// Priority-flood (Barnes et al., 2014) from the grid border inwards. With a
+/// positive `epsilon`, filled depressions keep a tiny gradient towards their
+/// outflow so that flow routing over them never stalls.
+pub fn fill_depressions(positions: &[Vector3<f32>], epsilon: f32) -> Vec<f32> {
+    let mut filled: Vec<f32> = positions.iter().map(|v| v.z).collect();
+    let mut closed = vec![false; positions.len()];
+    let mut open = BinaryHeap::new();
+
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            if i == 0 || j == 0 || i == RESOLUTION - 1 || j == RESOLUTION - 1 {
+                let k = ind!(i, j) as usize;
+                closed[k] = true;
+                open.push(Lowest(filled[k], k));
+            }
+        }
+    }
+
+    while let Some(Lowest(h, k)) = open.pop() {
+        let (i, j) = (k as i32 / RESOLUTION, k as i32 % RESOLUTION);
+        for &(di, dj) in NEIGHBORS.iter() {
+            if !in_bounds(i + di, j + dj) {
+                continue;
+            }
+            let n = ind!(i + di, j + dj) as usize;
+            if closed[n] {
+                continue;
+            }
+            closed[n] = true;
+            if filled[n] <= h {
+                filled[n] = h + epsilon;
+            }
+            open.push(Lowest(filled[n], n));
+        }
+    }
+    filled
+}
+
+/// `fill_depressions` in place.
+pub fn fill_depressions_mut(positions: &mut [Vector3<f32>], epsilon: f32) {
+    let filled = fill_depressions(positions, epsilon);
+    for (v, z) in positions.iter_mut().zip(filled) {
+        v.z = z;
+    }
+}
+
+/// Fills the depressions of the heightfield and groups the flooded vertices
+/// into lakes.
+pub fn detect_lakes(positions: &[Vector3<f32>]) -> LakeMap {
+    let filled = fill_depressions(positions, 0.0);
+    let flooded: Vec<bool> = positions
+        .iter()
+        .zip(filled.iter())
+        .map(|(v, &z)| z - v.z > LAKE_MIN_DEPTH)
+        .collect();
+
+    let mut lake_ids = vec![None; positions.len()];
+    let mut lakes = vec![];
+    for seed in 0..positions.len() {
+        if !flooded[seed] || lake_ids[seed].is_some() {
+            continue;
+        }
+        let id = lakes.len();
+        let mut cells = vec![];
+        let mut outflow: Option<usize> = None;
+        let mut stack = vec![seed];
+        lake_ids[seed] = Some(id);
+        while let Some(k) = stack.pop() {
+            cells.push(k);
+            let (i, j) = (k as i32 / RESOLUTION, k as i32 % RESOLUTION);
+            for &(di, dj) in NEIGHBORS.iter() {
+                if !in_bounds(i + di, j + dj) {
+                    continue;
+                }
+                let n = ind!(i + di, j + dj) as usize;
+                if !flooded[n] {
+                    if outflow.is_none_or(|o| positions[n].z < positions[o].z) {
+                        outflow = Some(n);
+                    }
+                } else if lake_ids[n].is_none() {
+                    lake_ids[n] = Some(id);
+                    stack.push(n);
+                }
+            }
+        }
+        lakes.push(Lake {
+            level: cells.iter().map(|&k| filled[k]).fold(f32::MIN, f32::max),
+            cells,
+            outflow: outflow.expect("lakes never touch the grid border"),
+        });
+    }
+
+    LakeMap {
+        filled,
+        lake_ids,
+        lakes,
+    }
+}
+
+/// Flat water surfaces over every grid triangle touching a lake. Shore
+/// vertices are lifted to the lake level too and left for the depth test to
+/// clip against the terrain.
+pub fn lake_geometry(positions: &[Vector3<f32>], lake_map: &LakeMap) -> Geometry {
+    let mut surface = Vec::<Vector3<f32>>::new();
+    for i in 0..RESOLUTION - 1 {
+        for j in 0..RESOLUTION - 1 {
+            let quad = [
+                [ind!(i, j), ind!(i + 1, j), ind!(i, j + 1)],
+                [ind!(i, j + 1), ind!(i + 1, j), ind!(i + 1, j + 1)],
+            ];
+            for tri in quad.iter() {
+                let lake = tri.iter().find_map(|&k| lake_map.lake_ids[k as usize]);
+                if let Some(id) = lake {
+                    for &k in tri.iter() {
+                        let p = positions[k as usize];
+                        surface.push(Vector3::new(p.x, p.y, lake_map.lakes[id].level));
+                    }
+                }
+            }
+        }
+    }
+
+    Geometry {
+        triangles: (0..surface.len() as u32).collect(),
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                surface.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "normal",
+                3,
+                surface.iter().flat_map(|_| vec![0.0, 0.0, 1.0]).collect(),
+            ),
+            VertexAttrInfo::f32("texCoord", 2, surface.iter().flat_map(texcoord).collect()),
+        ]),
+    }
+}
+
+/// Every lake's level, area, volume and outflow point as an indented JSON
+/// array, in world units.
+pub fn lakes_json(positions: &[Vector3<f32>], lake_map: &LakeMap) -> String {
+    let lakes: Vec<String> = lake_map
+        .lakes
+        .iter()
+        .map(|lake| {
+            let depth: f32 = lake
+                .cells
+                .iter()
+                .map(|&k| lake_map.filled[k] - positions[k].z)
+                .sum();
+            let outflow = positions[lake.outflow];
+            format!(
+                "  {{\"level\": {:.3}, \"area\": {:.1}, \"volume\": {:.1}, \
+                 \"outflow\": [{:.2}, {:.2}]}}",
+                lake.level,
+                lake.cells.len() as f32 * STEP * STEP,
+                depth * STEP * STEP,
+                outflow.x,
+                outflow.y
+            )
+        })
+        .collect();
+    if lakes.is_empty() {
+        return String::from("[]");
+    }
+    format!("[\n{}\n]", lakes.join(",\n"))
+}
@@ -0,0 +1,360 @@
+use nalgebra::Vector3;
+
+use super::Heightmap;
+use crate::utils::webgl::{Geometry, VertexAttrInfo, VertexAttrs};
+
+#[derive(Debug)]
+pub struct LodNode {
+    /// 0 for full-resolution leaves, growing towards the root.
+    pub level: usize,
+    /// First vertex of the tile.
+    pub i0: usize,
+    pub j0: usize,
+    /// Cells of the full-resolution grid the tile spans along each side.
+    pub cells: usize,
+    /// Largest height difference between the tile and the full-resolution
+    /// heightmap, including that of all its descendants.
+    pub error: f32,
+    pub min_z: f32,
+    pub max_z: f32,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// Quadtree of tiles over a heightmap. Every tile has `tile_cells` cells per
+/// side, each cell covering `2^level` cells of the heightmap.
+#[derive(Debug)]
+pub struct QuadTree {
+    pub tile_cells: usize,
+    /// Nodes with the root first.
+    pub nodes: Vec<LodNode>,
+}
+
+pub fn build_quadtree(map: &Heightmap, tile_cells: usize) -> QuadTree {
+    let mut depth = 0;
+    while tile_cells << depth < map.size - 1 {
+        depth += 1;
+    }
+    let mut tree = QuadTree {
+        tile_cells,
+        nodes: vec![],
+    };
+    build_node(map, &mut tree, None, depth, 0, 0);
+    tree
+}
+
+fn build_node(
+    map: &Heightmap,
+    tree: &mut QuadTree,
+    parent: Option<usize>,
+    level: usize,
+    i0: usize,
+    j0: usize,
+) -> usize {
+    let id = tree.nodes.len();
+    let cells = tree.tile_cells << level;
+    tree.nodes.push(LodNode {
+        level,
+        i0,
+        j0,
+        cells,
+        error: 0.0,
+        min_z: f32::MAX,
+        max_z: f32::MIN,
+        parent,
+        children: vec![],
+    });
+
+    let last = map.size - 1;
+    let mut children = vec![];
+    if level > 0 {
+        let half = cells / 2;
+        for &(ci, cj) in [
+            (i0, j0),
+            (i0 + half, j0),
+            (i0, j0 + half),
+            (i0 + half, j0 + half),
+        ]
+        .iter()
+        {
+            if ci < last && cj < last {
+                children.push(build_node(map, tree, Some(id), level - 1, ci, cj));
+            }
+        }
+    }
+
+    let (is, js) = (
+        samples(i0, cells, 1 << level, last),
+        samples(j0, cells, 1 << level, last),
+    );
+    let mut error = approximation_error(map, &is, &js);
+    let (mut min_z, mut max_z) = (f32::MAX, f32::MIN);
+    for i in i0..=is[is.len() - 1] {
+        for j in j0..=js[js.len() - 1] {
+            min_z = min_z.min(map.height(i, j));
+            max_z = max_z.max(map.height(i, j));
+        }
+    }
+    for &c in children.iter() {
+        error = error.max(tree.nodes[c].error);
+    }
+
+    let node = &mut tree.nodes[id];
+    node.error = error;
+    node.min_z = min_z;
+    node.max_z = max_z;
+    node.children = children;
+    id
+}
+
+/// Vertex coordinates of a tile along one axis, every `stride` vertices
+/// from `start`, clipped to the heightmap.
+fn samples(start: usize, cells: usize, stride: usize, last: usize) -> Vec<usize> {
+    let end = usize::min(start + cells, last);
+    let mut coords: Vec<usize> = (start..end).step_by(stride).collect();
+    coords.push(end);
+    coords
+}
+
+/// Largest height difference between the heightmap and the tile sampled at
+/// `is * js`, triangulated like `terrain_geometry`.
+fn approximation_error(map: &Heightmap, is: &[usize], js: &[usize]) -> f32 {
+    let mut error = 0.0f32;
+    for a in 0..is.len() - 1 {
+        for b in 0..js.len() - 1 {
+            let (i0, i1, j0, j1) = (is[a], is[a + 1], js[b], js[b + 1]);
+            if i1 - i0 <= 1 && j1 - j0 <= 1 {
+                continue;
+            }
+            let z = |i: usize, j: usize| map.height(i, j);
+            for i in i0..=i1 {
+                for j in j0..=j1 {
+                    let fu = (i - i0) as f32 / (i1 - i0) as f32;
+                    let fv = (j - j0) as f32 / (j1 - j0) as f32;
+                    let approx = if fu + fv <= 1.0 {
+                        z(i0, j0) + fu * (z(i1, j0) - z(i0, j0)) + fv * (z(i0, j1) - z(i0, j0))
+                    } else {
+                        z(i1, j1)
+                            + (1.0 - fu) * (z(i0, j1) - z(i1, j1))
+                            + (1.0 - fv) * (z(i1, j0) - z(i1, j1))
+                    };
+                    error = error.max((approx - map.height(i, j)).abs());
+                }
+            }
+        }
+    }
+    error
+}
+
+/// Coarsest tiles covering the heightmap whose error, projected to the
+/// screen from `eye`, is within `max_error` pixels. `pixels_per_radian` is
+/// the viewport height over `2 * tan(fov / 2)`.
+pub fn select_tiles(
+    tree: &QuadTree,
+    map: &Heightmap,
+    eye: &Vector3<f32>,
+    pixels_per_radian: f32,
+    max_error: f32,
+) -> Vec<usize> {
+    let mut selected = vec![];
+    let mut stack = vec![0];
+    while let Some(id) = stack.pop() {
+        let node = &tree.nodes[id];
+        let lo = map.position(node.i0, node.j0);
+        let last = map.size - 1;
+        let hi = map.position(
+            usize::min(node.i0 + node.cells, last),
+            usize::min(node.j0 + node.cells, last),
+        );
+        let nearest = Vector3::new(
+            eye.x.clamp(lo.x, hi.x),
+            eye.y.clamp(lo.y, hi.y),
+            eye.z.clamp(node.min_z, node.max_z),
+        );
+        let distance = f32::max((eye - nearest).norm(), f32::EPSILON);
+        if node.children.is_empty() || node.error * pixels_per_radian / distance <= max_error {
+            selected.push(id);
+        } else {
+            stack.extend(node.children.iter());
+        }
+    }
+    selected
+}
+
+/// Mesh of a tile, with skirts hanging from its border down by its parent's
+/// error so that cracks towards coarser neighbours are covered.
+pub fn tile_geometry(tree: &QuadTree, map: &Heightmap, id: usize) -> Geometry {
+    let node = &tree.nodes[id];
+    let last = map.size - 1;
+    let stride = 1 << node.level;
+    let (is, js) = (
+        samples(node.i0, node.cells, stride, last),
+        samples(node.j0, node.cells, stride, last),
+    );
+    let skirt_depth = map.spacing + node.parent.map_or(node.error, |p| tree.nodes[p].error);
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    for &i in is.iter() {
+        for &j in js.iter() {
+            positions.push(map.position(i, j));
+            normals.push(map.normal(i, j));
+        }
+    }
+    let (n, m) = (is.len() as u32, js.len() as u32);
+    let at = |a: u32, b: u32| a * m + b;
+    let mut triangles = vec![];
+    for a in 0..n - 1 {
+        for b in 0..m - 1 {
+            triangles.extend_from_slice(&[at(a, b), at(a + 1, b), at(a, b + 1)]);
+            triangles.extend_from_slice(&[at(a, b + 1), at(a + 1, b), at(a + 1, b + 1)]);
+        }
+    }
+
+    let border: Vec<u32> = (0..n - 1)
+        .map(|a| at(a, 0))
+        .chain((0..m - 1).map(|b| at(n - 1, b)))
+        .chain((1..n).rev().map(|a| at(a, m - 1)))
+        .chain((1..m).rev().map(|b| at(0, b)))
+        .collect();
+    let first_skirt = positions.len() as u32;
+    for &k in border.iter() {
+        positions.push(positions[k as usize] - Vector3::new(0.0, 0.0, skirt_depth));
+        normals.push(normals[k as usize]);
+    }
+    for k in 0..border.len() as u32 {
+        let next = (k + 1) % border.len() as u32;
+        let (top, top_next) = (border[k as usize], border[next as usize]);
+        let (bottom, bottom_next) = (first_skirt + k, first_skirt + next);
+        triangles.extend_from_slice(&[top, bottom, top_next]);
+        triangles.extend_from_slice(&[top_next, bottom, bottom_next]);
+    }
+
+    Geometry {
+        triangles,
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "normal",
+                3,
+                normals.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "texCoord",
+                2,
+                positions.iter().flat_map(|p| map.texcoord(p)).collect(),
+            ),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::generate_heightfield;
+    use crate::utils::random::Rng;
+
+    const TILE_CELLS: usize = 16;
+
+    fn quadtree() -> (Heightmap, QuadTree) {
+        let map = Heightmap::from_positions(&generate_heightfield(&mut Rng::new(5)));
+        let tree = build_quadtree(&map, TILE_CELLS);
+        (map, tree)
+    }
+
+    #[test]
+    fn errors_grow_towards_the_root() {
+        let (_, tree) = quadtree();
+        for node in tree.nodes.iter() {
+            for &c in node.children.iter() {
+                assert!(tree.nodes[c].error <= node.error);
+                assert_eq!(tree.nodes[c].level + 1, node.level);
+            }
+            if node.level == 0 {
+                assert_eq!(node.error, 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn selection_covers_every_cell_once() {
+        let (map, tree) = quadtree();
+        let last = map.size - 1;
+        for eye in [
+            Vector3::new(-240.0, -240.0, 120.0),
+            Vector3::new(0.0, 0.0, 400.0),
+            Vector3::new(2000.0, 0.0, 100.0),
+        ] {
+            let tiles = select_tiles(&tree, &map, &eye, 500.0, 2.0);
+            let mut covered = vec![0; last * last];
+            for &id in tiles.iter() {
+                let node = &tree.nodes[id];
+                for i in node.i0..usize::min(node.i0 + node.cells, last) {
+                    for j in node.j0..usize::min(node.j0 + node.cells, last) {
+                        covered[i * last + j] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1));
+        }
+    }
+
+    #[test]
+    fn refines_near_the_eye() {
+        let (map, tree) = quadtree();
+        let eye = map.position(0, 0) + Vector3::new(0.0, 0.0, 5.0);
+        let tiles = select_tiles(&tree, &map, &eye, 50.0, 2.0);
+        let level_at = |i: usize, j: usize| {
+            tiles
+                .iter()
+                .map(|&id| &tree.nodes[id])
+                .find(|n| {
+                    (n.i0..n.i0 + n.cells).contains(&i) && (n.j0..n.j0 + n.cells).contains(&j)
+                })
+                .unwrap()
+                .level
+        };
+        assert_eq!(level_at(0, 0), 0);
+        assert!(level_at(90, 90) > 0);
+        let far = select_tiles(&tree, &map, &Vector3::new(1e6, 0.0, 0.0), 50.0, 2.0);
+        assert_eq!(far, vec![0]);
+    }
+
+    #[test]
+    fn skirts_hang_below_the_border() {
+        let (map, tree) = quadtree();
+        let id = tree.nodes.iter().position(|n| n.level == 1).unwrap();
+        let node = &tree.nodes[id];
+        let geometry = tile_geometry(&tree, &map, id);
+        let positions: Vec<Vector3<f32>> = geometry
+            .attributes
+            .get("position")
+            .and_then(|a| a.data.as_f32())
+            .unwrap()
+            .chunks(3)
+            .map(|p| Vector3::new(p[0], p[1], p[2]))
+            .collect();
+        let side = node.cells / 2 + 1;
+        let (grid, skirt) = positions.split_at(side * side);
+        assert_eq!(skirt.len(), 4 * (side - 1));
+        let depth = map.spacing + tree.nodes[node.parent.unwrap()].error;
+        for s in skirt.iter() {
+            let top = grid
+                .iter()
+                .find(|g| g.xy() == s.xy())
+                .expect("skirt vertex under a tile vertex");
+            assert!((top.z - depth - s.z).abs() < 1e-3);
+        }
+        // Every border vertex has its skirt.
+        let (lo, hi) = (map.position(node.i0, node.j0), grid[grid.len() - 1]);
+        let border = grid
+            .iter()
+            .filter(|g| g.x == lo.x || g.y == lo.y || g.x == hi.x || g.y == hi.y)
+            .count();
+        assert_eq!(border, skirt.len());
+    }
+}
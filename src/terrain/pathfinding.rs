@@ -0,0 +1,112 @@
+use nalgebra::{Vector2, Vector3};
+use std::collections::BinaryHeap;
+
+use super::{in_bounds, nearest_vertex, Lowest, NEIGHBORS, RESOLUTION};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    /// Guided by the straight-line distance to the goal.
+    AStar,
+    /// Uninformed; explores in all directions.
+    Dijkstra,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathCost {
+    /// Cost per unit of horizontal distance.
+    pub distance: f32,
+    /// Extra cost per degree of slope, per unit of horizontal distance.
+    pub slope: f32,
+    /// Extra cost per unit of height climbed.
+    pub climb: f32,
+    /// Extra cost per unit of height descended.
+    pub descent: f32,
+    /// Extra cost per unit of horizontal distance through water.
+    pub water: f32,
+    /// Slope, in degrees, beyond which a step is impassable.
+    pub max_slope: f32,
+}
+
+impl Default for PathCost {
+    fn default() -> PathCost {
+        PathCost {
+            distance: 1.0,
+            slope: 0.05,
+            climb: 1.0,
+            descent: 0.5,
+            water: 10.0,
+            max_slope: 35.0,
+        }
+    }
+}
+
+/// Cheapest 8-connected route over the grid between the vertices closest to
+/// `start` and `goal`, or `None` if the goal can't be reached. `water` marks
+/// the vertices under water, if any.
+pub fn find_path(
+    positions: &[Vector3<f32>],
+    water: Option<&[bool]>,
+    start: &Vector2<f32>,
+    goal: &Vector2<f32>,
+    cost: &PathCost,
+    search: Search,
+) -> Option<Vec<Vector3<f32>>> {
+    let start = nearest_vertex(start.x, start.y);
+    let goal = nearest_vertex(goal.x, goal.y);
+    let wet = |k: usize| water.is_some_and(|w| w[k]);
+    let heuristic = |k: usize| match search {
+        // Every other term is non-negative, so this never overestimates.
+        Search::AStar => cost.distance * (positions[k] - positions[goal]).xy().norm(),
+        Search::Dijkstra => 0.0,
+    };
+    let max_grade = cost.max_slope.to_radians().tan();
+
+    let mut best = vec![f32::INFINITY; positions.len()];
+    let mut came_from = vec![None; positions.len()];
+    let mut closed = vec![false; positions.len()];
+    let mut open = BinaryHeap::new();
+    best[start] = 0.0;
+    open.push(Lowest(heuristic(start), start));
+
+    while let Some(Lowest(_, k)) = open.pop() {
+        if closed[k] {
+            continue;
+        }
+        closed[k] = true;
+        if k == goal {
+            let mut path = vec![positions[k]];
+            let mut k = k;
+            while let Some(prev) = came_from[k] {
+                path.push(positions[prev]);
+                k = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let (i, j) = (k as i32 / RESOLUTION, k as i32 % RESOLUTION);
+        for &(di, dj) in NEIGHBORS.iter() {
+            if !in_bounds(i + di, j + dj) {
+                continue;
+            }
+            let n = ind!(i + di, j + dj) as usize;
+            let run = (positions[n] - positions[k]).xy().norm();
+            let rise = positions[n].z - positions[k].z;
+            if rise.abs() > max_grade * run {
+                continue;
+            }
+            let mut step = run
+                * (cost.distance + cost.slope * (rise.abs() / run).atan().to_degrees())
+                + cost.climb * rise.max(0.0)
+                + cost.descent * (-rise).max(0.0);
+            if wet(k) || wet(n) {
+                step += cost.water * run;
+            }
+            if best[k] + step < best[n] {
+                best[n] = best[k] + step;
+                came_from[n] = Some(k);
+                open.push(Lowest(best[n] + heuristic(n), n));
+            }
+        }
+    }
+    None
+}
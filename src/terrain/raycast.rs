@@ -0,0 +1,149 @@
+use nalgebra::Vector3;
+
+use super::{BOX_SIZE, RESOLUTION, STEP};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub point: Vector3<f32>,
+    /// Face normal of the triangle hit, pointing up.
+    pub normal: Vector3<f32>,
+    /// Index of the triangle hit, in the order of `terrain_geometry`.
+    pub triangle: usize,
+    /// Distance from the ray origin to the hit point.
+    pub distance: f32,
+}
+
+/// First intersection of a ray with the terrain surface within
+/// `max_distance`. Walks the grid cells the ray crosses in order (Amanatides
+/// & Woo) and tests the two triangles of each.
+pub fn raycast(
+    positions: &[Vector3<f32>],
+    origin: &Vector3<f32>,
+    direction: &Vector3<f32>,
+    max_distance: f32,
+) -> Option<RayHit> {
+    let dir = direction.try_normalize(f32::EPSILON)?;
+    let lo = -0.5 * BOX_SIZE;
+    let hi = lo + STEP * (RESOLUTION - 1) as f32;
+
+    // Clip the ray against the footprint of the grid.
+    let (mut t_enter, mut t_exit) = (0.0f32, max_distance);
+    for axis in 0..2 {
+        if dir[axis].abs() < f32::EPSILON {
+            if origin[axis] < lo || origin[axis] > hi {
+                return None;
+            }
+        } else {
+            let ta = (lo - origin[axis]) / dir[axis];
+            let tb = (hi - origin[axis]) / dir[axis];
+            t_enter = t_enter.max(ta.min(tb));
+            t_exit = t_exit.min(ta.max(tb));
+        }
+    }
+    if t_enter > t_exit {
+        return None;
+    }
+
+    let start = origin + t_enter * dir;
+    let cell = |x: f32| i32::clamp(((x - lo) / STEP) as i32, 0, RESOLUTION - 2);
+    let (mut i, mut j) = (cell(start.x), cell(start.y));
+
+    let step = |d: f32| if d > 0.0 { 1 } else { -1 };
+    let (step_i, step_j) = (step(dir.x), step(dir.y));
+    let first_boundary = |c: i32, s: i32, o: f32, d: f32| {
+        if d.abs() < f32::EPSILON {
+            f32::INFINITY
+        } else {
+            let edge = lo + STEP * (if s > 0 { c + 1 } else { c }) as f32;
+            (edge - o) / d
+        }
+    };
+    let mut t_max_x = first_boundary(i, step_i, origin.x, dir.x);
+    let mut t_max_y = first_boundary(j, step_j, origin.y, dir.y);
+    let t_delta_x = STEP / dir.x.abs();
+    let t_delta_y = STEP / dir.y.abs();
+
+    loop {
+        let hit = cell_triangles(i, j)
+            .iter()
+            .enumerate()
+            .filter_map(|(k, tri)| {
+                let [a, b, c] = tri.map(|v| positions[v]);
+                intersect_triangle(origin, &dir, &a, &b, &c)
+                    .filter(|&t| t <= max_distance)
+                    .map(|t| (t, k, (b - a).cross(&(c - a)).normalize()))
+            })
+            .min_by(|x, y| x.0.total_cmp(&y.0));
+        if let Some((t, k, normal)) = hit {
+            return Some(RayHit {
+                point: origin + t * dir,
+                normal,
+                triangle: 2 * (i * (RESOLUTION - 1) + j) as usize + k,
+                distance: t,
+            });
+        }
+
+        if t_max_x < t_max_y {
+            if t_max_x > t_exit {
+                return None;
+            }
+            i += step_i;
+            t_max_x += t_delta_x;
+        } else {
+            if t_max_y > t_exit {
+                return None;
+            }
+            j += step_j;
+            t_max_y += t_delta_y;
+        }
+        if !(0..RESOLUTION - 1).contains(&i) || !(0..RESOLUTION - 1).contains(&j) {
+            return None;
+        }
+    }
+}
+
+/// Vertex indices of the two triangles of cell `(i, j)`, wound like
+/// `terrain_geometry`.
+fn cell_triangles(i: i32, j: i32) -> [[usize; 3]; 2] {
+    [
+        [
+            ind!(i, j) as usize,
+            ind!(i + 1, j) as usize,
+            ind!(i, j + 1) as usize,
+        ],
+        [
+            ind!(i, j + 1) as usize,
+            ind!(i + 1, j) as usize,
+            ind!(i + 1, j + 1) as usize,
+        ],
+    ]
+}
+
+/// Möller–Trumbore ray/triangle intersection, returning the distance along
+/// the (normalized) direction.
+fn intersect_triangle(
+    origin: &Vector3<f32>,
+    dir: &Vector3<f32>,
+    a: &Vector3<f32>,
+    b: &Vector3<f32>,
+    c: &Vector3<f32>,
+) -> Option<f32> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let s = origin - a;
+    let u = s.dot(&p) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&e1);
+    let v = dir.dot(&q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some(e2.dot(&q) / det).filter(|&t| t >= 0.0)
+}
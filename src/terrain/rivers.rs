@@ -0,0 +1,281 @@
+use nalgebra::Vector3;
+use std::f32::consts::FRAC_PI_4;
+
+use super::{in_bounds, lakes::fill_depressions_mut, NEIGHBORS, RESOLUTION, STEP};
+
+/// Gradient imposed across filled depressions so they still drain.
+const DRAINAGE_EPSILON: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowRouting {
+    /// All flow goes to the steepest of the eight neighbours.
+    D8,
+    /// Flow is split between the two neighbours bracketing the steepest
+    /// downslope direction (Tarboton, 1997).
+    DInfinity,
+}
+
+#[derive(Debug, Clone)]
+pub struct RiverParams {
+    pub routing: FlowRouting,
+    /// Route flow over the depression-filled surface so it doesn't stop in
+    /// pits.
+    pub fill_depressions: bool,
+    /// Minimum contributing area, in cells, for a cell to become a channel.
+    pub threshold: f32,
+    /// River bed width per square root of discharge.
+    pub width_scale: f32,
+    /// River bed depth per square root of discharge.
+    pub depth_scale: f32,
+}
+
+impl Default for RiverParams {
+    fn default() -> RiverParams {
+        RiverParams {
+            routing: FlowRouting::D8,
+            fill_depressions: true,
+            threshold: 200.0,
+            width_scale: 0.5,
+            depth_scale: 0.15,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RiverNetwork {
+    /// Contributing area of every vertex, in cells (including itself).
+    pub accumulation: Vec<f32>,
+    /// Rivers traced from each source down to the outlet, or to where they
+    /// join a river traced before them, following the carved bed.
+    pub polylines: Vec<Vec<Vector3<f32>>>,
+}
+
+/// Extracts the river network and carves its beds into the heightfield.
+pub fn carve_rivers_mut(positions: &mut [Vector3<f32>], params: &RiverParams) -> RiverNetwork {
+    let mut routed = positions.to_vec();
+    if params.fill_depressions {
+        fill_depressions_mut(&mut routed, DRAINAGE_EPSILON);
+    }
+    let accumulation = flow_accumulation(&routed, params.routing);
+    let reaches = channel_reaches(&routed, &accumulation, params.threshold, params.routing);
+
+    let original: Vec<f32> = positions.iter().map(|v| v.z).collect();
+    for reach in reaches.iter() {
+        for &k in reach {
+            let q = accumulation[k].sqrt();
+            let half_width = 0.5 * params.width_scale * q;
+            let depth = params.depth_scale * q;
+            let reach_cells = (half_width / STEP).ceil() as i32;
+            let (i, j) = (k as i32 / RESOLUTION, k as i32 % RESOLUTION);
+            for ni in i - reach_cells..=i + reach_cells {
+                for nj in j - reach_cells..=j + reach_cells {
+                    if !in_bounds(ni, nj) {
+                        continue;
+                    }
+                    let n = ind!(ni, nj) as usize;
+                    let r = (positions[n] - positions[k]).xy().norm() / half_width.max(STEP);
+                    if r < 1.0 {
+                        let bed = original[k] - depth * (1.0 - r * r);
+                        positions[n].z = positions[n].z.min(bed);
+                    }
+                }
+            }
+        }
+    }
+
+    RiverNetwork {
+        accumulation,
+        polylines: reaches
+            .iter()
+            .map(|reach| reach.iter().map(|&k| positions[k]).collect())
+            .collect(),
+    }
+}
+
+/// Steepest-descent receiver of every vertex, or `None` for pits and
+/// outlets.
+pub fn d8_receivers(positions: &[Vector3<f32>]) -> Vec<Option<usize>> {
+    let mut receivers = vec![None; positions.len()];
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            let k = ind!(i, j) as usize;
+            let mut steepest = 0.0;
+            for &(di, dj) in NEIGHBORS.iter() {
+                if !in_bounds(i + di, j + dj) {
+                    continue;
+                }
+                let n = ind!(i + di, j + dj) as usize;
+                let slope =
+                    (positions[k].z - positions[n].z) / (positions[k] - positions[n]).xy().norm();
+                if slope > steepest {
+                    steepest = slope;
+                    receivers[k] = Some(n);
+                }
+            }
+        }
+    }
+    receivers
+}
+
+/// Receivers of every vertex with the fraction of flow each one gets.
+pub fn flow_directions(positions: &[Vector3<f32>], routing: FlowRouting) -> Vec<Vec<(usize, f32)>> {
+    match routing {
+        FlowRouting::D8 => d8_receivers(positions)
+            .into_iter()
+            .map(|r| r.map(|n| vec![(n, 1.0)]).unwrap_or_default())
+            .collect(),
+        FlowRouting::DInfinity => dinf_receivers(positions),
+    }
+}
+
+/// Contributing area of every vertex, in cells, accumulated from the highest
+/// vertex downwards.
+pub fn flow_accumulation(positions: &[Vector3<f32>], routing: FlowRouting) -> Vec<f32> {
+    let receivers = flow_directions(positions, routing);
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|&a, &b| positions[b].z.total_cmp(&positions[a].z));
+
+    let mut accumulation = vec![1.0; positions.len()];
+    for k in order {
+        for &(n, w) in receivers[k].iter() {
+            accumulation[n] += w * accumulation[k];
+        }
+    }
+    accumulation
+}
+
+fn dinf_receivers(positions: &[Vector3<f32>]) -> Vec<Vec<(usize, f32)>> {
+    let mut receivers = vec![vec![]; positions.len()];
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            let k = ind!(i, j) as usize;
+            let e0 = positions[k].z;
+            let mut best: Option<(f32, f32, usize, usize)> = None;
+            for f in 0..8usize {
+                let (ci, cj) = NEIGHBORS[f.div_ceil(2) * 2 % 8];
+                let (di, dj) = NEIGHBORS[f / 2 * 2 + 1];
+                if !in_bounds(i + ci, j + cj) || !in_bounds(i + di, j + dj) {
+                    continue;
+                }
+                let n1 = ind!(i + ci, j + cj) as usize;
+                let n2 = ind!(i + di, j + dj) as usize;
+                let s1 = (e0 - positions[n1].z) / STEP;
+                let s2 = (positions[n1].z - positions[n2].z) / STEP;
+                let (mut r, mut s) = (f32::atan2(s2, s1), s1.hypot(s2));
+                if r < 0.0 {
+                    r = 0.0;
+                    s = s1;
+                } else if r > FRAC_PI_4 {
+                    r = FRAC_PI_4;
+                    s = (e0 - positions[n2].z) / (STEP * 2f32.sqrt());
+                }
+                if s > 0.0 && best.is_none_or(|(bs, ..)| s > bs) {
+                    best = Some((s, r, n1, n2));
+                }
+            }
+            if let Some((_, r, n1, n2)) = best {
+                let w2 = r / FRAC_PI_4;
+                receivers[k] = vec![(n1, 1.0 - w2), (n2, w2)]
+                    .into_iter()
+                    .filter(|&(_, w)| w > 0.0)
+                    .collect();
+            }
+        }
+    }
+    receivers
+}
+
+/// Traces channel cells as lists of vertex indices, following the receiver
+/// getting most of the flow from each source down to the outlet or an
+/// already traced channel.
+fn channel_reaches(
+    positions: &[Vector3<f32>],
+    accumulation: &[f32],
+    threshold: f32,
+    routing: FlowRouting,
+) -> Vec<Vec<usize>> {
+    let receivers: Vec<Option<usize>> = flow_directions(positions, routing)
+        .iter()
+        .map(|r| r.iter().max_by(|a, b| a.1.total_cmp(&b.1)).map(|&(n, _)| n))
+        .collect();
+    let is_channel: Vec<bool> = accumulation.iter().map(|&a| a >= threshold).collect();
+
+    let mut donors = vec![0; positions.len()];
+    for (k, r) in receivers.iter().enumerate() {
+        if let (true, Some(n)) = (is_channel[k], *r) {
+            donors[n] += 1;
+        }
+    }
+
+    let mut visited = vec![false; positions.len()];
+    let mut reaches = vec![];
+    for source in (0..positions.len()).filter(|&k| is_channel[k] && donors[k] == 0) {
+        let mut reach = vec![source];
+        let mut k = source;
+        visited[k] = true;
+        while let Some(n) = receivers[k].filter(|&n| is_channel[n]) {
+            reach.push(n);
+            if visited[n] {
+                break;
+            }
+            visited[n] = true;
+            k = n;
+        }
+        reaches.push(reach);
+    }
+    reaches
+}
+
+/// River network as a GeoJSON feature collection of 3D line strings.
+pub fn rivers_geojson(network: &RiverNetwork) -> String {
+    let features: Vec<String> = network
+        .polylines
+        .iter()
+        .map(|line| {
+            let coordinates: Vec<String> = line
+                .iter()
+                .map(|p| format!("[{:.2},{:.2},{:.2}]", p.x, p.y, p.z))
+                .collect();
+            format!(
+                "{{\"type\":\"Feature\",\"properties\":{{}},\
+                 \"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}}}}",
+                coordinates.join(",")
+            )
+        })
+        .collect();
+    format!(
+        "{{\"type\":\"FeatureCollection\",\"features\":[{}]}}",
+        features.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::generate_heightfield;
+    use crate::utils::random::Rng;
+
+    #[test]
+    fn reaches_follow_the_routing() {
+        let mut positions = generate_heightfield(&mut Rng::new(3));
+        fill_depressions_mut(&mut positions, DRAINAGE_EPSILON);
+        for routing in [FlowRouting::D8, FlowRouting::DInfinity] {
+            let directions = flow_directions(&positions, routing);
+            let accumulation = flow_accumulation(&positions, routing);
+            let reaches = channel_reaches(&positions, &accumulation, 200.0, routing);
+            assert!(!reaches.is_empty());
+            for reach in reaches.iter() {
+                for step in reach.windows(2) {
+                    let (k, n) = (step[0], step[1]);
+                    let dominant = directions[k]
+                        .iter()
+                        .max_by(|a, b| a.1.total_cmp(&b.1))
+                        .map(|&(n, _)| n);
+                    assert_eq!(dominant, Some(n));
+                    assert!(positions[n].z < positions[k].z);
+                    assert!(accumulation[n] >= 200.0);
+                }
+            }
+        }
+    }
+}
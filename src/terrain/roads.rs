@@ -0,0 +1,194 @@
+use nalgebra::{Vector2, Vector3};
+
+use super::{
+    height_at,
+    pathfinding::{find_path, PathCost, Search},
+    smoothstep, BOX_SIZE, RESOLUTION, STEP,
+};
+
+/// Every how many path vertices a spline control point is kept; the rest is
+/// grid staircase.
+const CONTROL_SPACING: usize = 3;
+/// Distance between samples of the road centerline.
+const SAMPLE_SPACING: f32 = 0.5 * STEP;
+
+#[derive(Debug, Clone)]
+pub struct RoadParams {
+    /// Width of the flat road bed.
+    pub width: f32,
+    /// Width of the banked shoulder blending the bed into the terrain on
+    /// each side.
+    pub shoulder: f32,
+    /// Steepest slope, in degrees, the route may take.
+    pub max_slope: f32,
+    /// Number of centerline samples averaged to even out the road's
+    /// gradient.
+    pub grade_window: usize,
+}
+
+impl Default for RoadParams {
+    fn default() -> RoadParams {
+        RoadParams {
+            width: 8.0,
+            shoulder: 10.0,
+            max_slope: 35.0,
+            grade_window: 15,
+        }
+    }
+}
+
+/// Waypoints of a road crossing the map from west to east, bending north
+/// through the middle.
+pub fn crossing_waypoints() -> Vec<Vector2<f32>> {
+    vec![
+        Vector2::new(-0.45 * BOX_SIZE, 0.0),
+        Vector2::new(0.0, 0.2 * BOX_SIZE),
+        Vector2::new(0.45 * BOX_SIZE, 0.0),
+    ]
+}
+
+/// Routes a road through the waypoints, smooths it into a Catmull-Rom
+/// spline kept on the map and carves its bed into the heightfield. Returns
+/// the centerline at road height, or `None` if some leg has no route within
+/// `max_slope`.
+pub fn build_road_mut(
+    positions: &mut [Vector3<f32>],
+    waypoints: &[Vector2<f32>],
+    params: &RoadParams,
+) -> Option<Vec<Vector3<f32>>> {
+    let cost = PathCost {
+        max_slope: params.max_slope,
+        ..PathCost::default()
+    };
+    let mut route = Vec::<Vector3<f32>>::new();
+    for leg in waypoints.windows(2) {
+        let path = find_path(positions, None, &leg[0], &leg[1], &cost, Search::AStar)?;
+        let skip = if route.is_empty() { 0 } else { 1 };
+        route.extend(path.into_iter().skip(skip));
+    }
+    if route.len() < 2 {
+        return None;
+    }
+
+    let mut controls: Vec<Vector2<f32>> = route
+        .iter()
+        .step_by(CONTROL_SPACING)
+        .map(|p| p.xy())
+        .collect();
+    if !(route.len() - 1).is_multiple_of(CONTROL_SPACING) {
+        controls.push(route[route.len() - 1].xy());
+    }
+    // The spline can overshoot a route along the border.
+    let (lo, hi) = (
+        -0.5 * BOX_SIZE,
+        -0.5 * BOX_SIZE + STEP * (RESOLUTION - 1) as f32,
+    );
+    let mut centerline = catmull_rom(&controls)
+        .into_iter()
+        .map(|p| {
+            let (x, y) = (p.x.clamp(lo, hi), p.y.clamp(lo, hi));
+            height_at(positions, x, y).map(|z| Vector3::new(x, y, z))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    smooth_grade(&mut centerline, params.grade_window);
+
+    carve_road_mut(positions, &centerline, params);
+    Some(centerline)
+}
+
+/// Flattens the terrain to the centerline's height across the road bed and
+/// blends it back to the original terrain across the shoulders.
+pub fn carve_road_mut(
+    positions: &mut [Vector3<f32>],
+    centerline: &[Vector3<f32>],
+    params: &RoadParams,
+) {
+    let half_width = 0.5 * params.width;
+    for v in positions.iter_mut() {
+        let nearest = centerline
+            .windows(2)
+            .map(|seg| {
+                let (a, b) = (seg[0], seg[1]);
+                let ab = (b - a).xy();
+                let t = if ab.norm_squared() > 0.0 {
+                    ((v.xy() - a.xy()).dot(&ab) / ab.norm_squared()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let p = a.lerp(&b, t);
+                ((v.xy() - p.xy()).norm(), p.z)
+            })
+            .min_by(|x, y| x.0.total_cmp(&y.0));
+        if let Some((distance, road)) = nearest {
+            if distance < half_width + params.shoulder {
+                let blend = smoothstep(half_width, half_width + params.shoulder, distance);
+                v.z = road + blend * (v.z - road);
+            }
+        }
+    }
+}
+
+/// Uniform Catmull-Rom spline through the control points, sampled every
+/// `SAMPLE_SPACING`.
+fn catmull_rom(controls: &[Vector2<f32>]) -> Vec<Vector2<f32>> {
+    let n = controls.len();
+    let at = |k: isize| controls[k.clamp(0, n as isize - 1) as usize];
+    let mut samples = vec![controls[0]];
+    for k in 0..n as isize - 1 {
+        let (p0, p1, p2, p3) = (at(k - 1), at(k), at(k + 1), at(k + 2));
+        let steps = usize::max(1, ((p2 - p1).norm() / SAMPLE_SPACING).ceil() as usize);
+        for s in 1..=steps {
+            let t = s as f32 / steps as f32;
+            let (t2, t3) = (t * t, t * t * t);
+            samples.push(
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+            );
+        }
+    }
+    samples
+}
+
+/// Centered moving average of the centerline heights. The window narrows
+/// towards the ends so that these stay in place without a kink.
+fn smooth_grade(centerline: &mut [Vector3<f32>], window: usize) {
+    let heights: Vec<f32> = centerline.iter().map(|p| p.z).collect();
+    let last = heights.len() - 1;
+    for (k, p) in centerline.iter_mut().enumerate() {
+        let half = (window / 2).min(k).min(last - k);
+        let samples = &heights[k - half..=k + half];
+        p.z = samples.iter().sum::<f32>() / samples.len() as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_road_on_the_map() {
+        let mut positions = vec![];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                let (x, y) = (STEP * i as f32 - 250.0, STEP * j as f32 - 250.0);
+                positions.push(Vector3::new(x, y, 10.0));
+            }
+        }
+        // A route hugging the border, where the spline overshoots the
+        // corners.
+        let waypoints = [
+            Vector2::new(-250.0, -150.0),
+            Vector2::new(-250.0, -250.0),
+            Vector2::new(-150.0, -250.0),
+        ];
+        let road = build_road_mut(&mut positions, &waypoints, &RoadParams::default()).unwrap();
+        let last = -250.0 + STEP * (RESOLUTION - 1) as f32;
+        for p in road.iter() {
+            assert!((-250.0..=last).contains(&p.x) && (-250.0..=last).contains(&p.y));
+            assert!((p.z - 10.0).abs() < 1e-4);
+        }
+        assert!(positions.iter().all(|v| (v.z - 10.0).abs() < 1e-4));
+    }
+}
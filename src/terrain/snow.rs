@@ -0,0 +1,183 @@
+use nalgebra::Vector3;
+
+use super::{gradient, smoothstep, LIGHT, RESOLUTION, STEP};
+
+/// Height over which snow cover fades in above the snowline.
+const SNOW_TRANSITION: f32 = 20.0;
+/// Shallow-ice flux coefficient (Glen's law with n = 3).
+const GLEN_COEFFICIENT: f32 = 1e-7;
+/// Upper bound on the ice diffusivity keeping the explicit scheme stable.
+const MAX_DIFFUSIVITY: f32 = 0.2 * STEP * STEP;
+/// Bedrock removed per unit of ice moved across a cell face.
+const EROSION_RATE: f32 = 0.05;
+
+#[derive(Debug, Clone)]
+pub struct SnowParams {
+    /// Altitude above which snow starts to settle.
+    pub snowline: f32,
+    /// Snow thickness on flat ground well above the snowline.
+    pub max_depth: f32,
+    /// Slope, in degrees, beyond which snow slides off.
+    pub max_slope: f32,
+    /// Fraction of snow melted on slopes facing the light directly.
+    pub sun_melt: f32,
+    /// Altitude above which glaciers gain ice and below which they melt.
+    pub equilibrium_line: f32,
+    /// Ice gained or lost per iteration per unit of altitude from the
+    /// equilibrium line.
+    pub mass_balance: f32,
+    /// Number of glacial flow iterations; zero disables glaciers.
+    pub glacier_iterations: usize,
+}
+
+impl Default for SnowParams {
+    fn default() -> SnowParams {
+        SnowParams {
+            snowline: 150.0,
+            max_depth: 3.0,
+            max_slope: 50.0,
+            sun_melt: 0.6,
+            equilibrium_line: 140.0,
+            mass_balance: 0.01,
+            glacier_iterations: 200,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SnowCover {
+    /// Snow thickness of every vertex, for texturing.
+    pub snow: Vec<f32>,
+    /// Glacier ice thickness of every vertex.
+    pub ice: Vec<f32>,
+}
+
+/// Lets glaciers carve the bedrock, then covers the result with ice and
+/// snow. The heightfield ends up at the top of the snow.
+pub fn add_snow_mut(positions: &mut [Vector3<f32>], params: &SnowParams) -> SnowCover {
+    let ice = carve_glaciers_mut(positions, params);
+    for (v, h) in positions.iter_mut().zip(ice.iter()) {
+        v.z += h;
+    }
+    let snow = snow_thickness(positions, params);
+    for (v, h) in positions.iter_mut().zip(snow.iter()) {
+        v.z += h;
+    }
+    SnowCover { snow, ice }
+}
+
+/// Snow thickness from altitude, slope and exposure to the light, slopes
+/// facing `LIGHT` melting faster.
+pub fn snow_thickness(positions: &[Vector3<f32>], params: &SnowParams) -> Vec<f32> {
+    let light = LIGHT.normalize();
+    let max_slope = params.max_slope.to_radians();
+    let mut snow = vec![0.0; positions.len()];
+    for i in 0..RESOLUTION {
+        for j in 0..RESOLUTION {
+            let k = ind!(i, j) as usize;
+            let altitude = ((positions[k].z - params.snowline) / SNOW_TRANSITION).clamp(0.0, 1.0);
+            if altitude == 0.0 {
+                continue;
+            }
+            let g = gradient(positions, i, j);
+            let normal = Vector3::new(-g.x, -g.y, 1.0).normalize();
+            let slope = normal.z.acos();
+            let settling = 1.0 - smoothstep(0.5 * max_slope, max_slope, slope);
+            let exposure = 1.0 - params.sun_melt * normal.dot(&light).max(0.0);
+            snow[k] = params.max_depth * altitude * settling * exposure;
+        }
+    }
+    snow
+}
+
+/// Shallow-ice glacier flow over the bedrock. Ice accumulates above the
+/// equilibrium line, flows downhill and abrades its bed in proportion to the
+/// ice flux, which widens and deepens valleys into a U shape. Returns the
+/// final ice thickness.
+pub fn carve_glaciers_mut(positions: &mut [Vector3<f32>], params: &SnowParams) -> Vec<f32> {
+    let mut ice = vec![0.0; positions.len()];
+    for _ in 0..params.glacier_iterations {
+        for (h, v) in ice.iter_mut().zip(positions.iter()) {
+            let balance = params.mass_balance * (v.z + *h - params.equilibrium_line);
+            *h = f32::max(0.0, *h + balance.clamp(-1.0, 1.0));
+        }
+
+        let mut change = vec![0.0; positions.len()];
+        let mut abrasion = vec![0.0; positions.len()];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                for &(ni, nj) in [(i + 1, j), (i, j + 1)].iter() {
+                    if ni >= RESOLUTION || nj >= RESOLUTION {
+                        continue;
+                    }
+                    let (a, b) = (ind!(i, j) as usize, ind!(ni, nj) as usize);
+                    let drop = (positions[a].z + ice[a]) - (positions[b].z + ice[b]);
+                    let (from, to) = if drop > 0.0 { (a, b) } else { (b, a) };
+                    let grad = drop.abs() / STEP;
+                    let h = 0.5 * (ice[a] + ice[b]);
+                    let diffusivity =
+                        f32::min(MAX_DIFFUSIVITY, GLEN_COEFFICIENT * h.powi(5) * grad.powi(2));
+                    let moved = f32::min(0.25 * ice[from], diffusivity * grad / STEP);
+                    change[from] -= moved;
+                    change[to] += moved;
+                    abrasion[from] += 0.5 * moved;
+                    abrasion[to] += 0.5 * moved;
+                }
+            }
+        }
+
+        for k in 0..positions.len() {
+            ice[k] = f32::max(0.0, ice[k] + change[k]);
+            positions[k].z -= EROSION_RATE * abrasion[k];
+        }
+    }
+    ice
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(z: impl Fn(f32, f32) -> f32) -> Vec<Vector3<f32>> {
+        let mut positions = vec![];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                let (x, y) = (STEP * i as f32 - 250.0, STEP * j as f32 - 250.0);
+                positions.push(Vector3::new(x, y, z(x, y)));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn snow_settles_above_the_snowline() {
+        let params = SnowParams::default();
+        let below = grid(|_, _| params.snowline - 1.0);
+        assert!(snow_thickness(&below, &params).iter().all(|&s| s == 0.0));
+        let above = grid(|_, _| params.snowline + SNOW_TRANSITION);
+        let snow = snow_thickness(&above, &params);
+        assert!(snow.iter().all(|&s| s > 0.0 && s <= params.max_depth));
+    }
+
+    #[test]
+    fn glaciers_conserve_ice() {
+        let params = SnowParams {
+            mass_balance: 1.0,
+            glacier_iterations: 1,
+            ..SnowParams::default()
+        };
+        let mut positions = grid(|x, y| 250.0 - 0.5 * x.hypot(y));
+        let gained: f32 = positions
+            .iter()
+            .map(|v| (params.mass_balance * (v.z - params.equilibrium_line)).clamp(0.0, 1.0))
+            .sum();
+        let ice = carve_glaciers_mut(&mut positions, &params);
+        let total: f32 = ice.iter().sum();
+        assert!((total - gained).abs() < 1e-3 * gained);
+        // Some of it flowed below the equilibrium line.
+        assert!(ice
+            .iter()
+            .zip(positions.iter())
+            .any(|(&h, v)| h > 0.0 && v.z < params.equilibrium_line - 1.0));
+    }
+}
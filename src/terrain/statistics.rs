@@ -0,0 +1,115 @@
+use nalgebra::Vector3;
+
+use super::analysis::{roughness, slope, value_range};
+
+/// Number of relative heights the hypsometric curve is sampled at, besides
+/// zero.
+const HYPSOMETRIC_SAMPLES: usize = 10;
+/// Width of the slope histogram bins, in degrees.
+const SLOPE_BIN_WIDTH: f32 = 10.0;
+
+#[derive(Debug)]
+pub struct TerrainStatistics {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub std_dev: f32,
+    /// Fraction of the area at or above evenly spaced heights from `min`
+    /// (first) to `max` (last).
+    pub hypsometric_curve: Vec<f32>,
+    /// Area under the hypsometric curve, i.e. `(mean - min) / (max - min)`.
+    /// High for young, plateau-like terrain, low for eroded terrain.
+    pub hypsometric_integral: f32,
+    /// Number of vertices per `SLOPE_BIN_WIDTH` degrees of slope, from flat
+    /// to vertical.
+    pub slope_histogram: Vec<usize>,
+    pub mean_slope: f32,
+    /// Fraction of vertices under water.
+    pub water_coverage: f32,
+    pub mean_roughness: f32,
+    pub max_roughness: f32,
+}
+
+/// Summary statistics of the heightfield. `water` marks the vertices under
+/// water, if any.
+pub fn terrain_statistics(positions: &[Vector3<f32>], water: Option<&[bool]>) -> TerrainStatistics {
+    let n = positions.len() as f32;
+    let (min, max) = value_range(&positions.iter().map(|v| v.z).collect::<Vec<_>>());
+    let mean = positions.iter().map(|v| v.z).sum::<f32>() / n;
+    let variance = positions.iter().map(|v| (v.z - mean).powi(2)).sum::<f32>() / n;
+
+    let hypsometric_curve = (0..=HYPSOMETRIC_SAMPLES)
+        .map(|k| {
+            let level = min + (max - min) * k as f32 / HYPSOMETRIC_SAMPLES as f32;
+            positions.iter().filter(|v| v.z >= level).count() as f32 / n
+        })
+        .collect();
+
+    let slopes = slope(positions);
+    let bins = (90.0 / SLOPE_BIN_WIDTH).ceil() as usize;
+    let rough = roughness(positions);
+
+    TerrainStatistics {
+        min,
+        max,
+        mean,
+        std_dev: variance.sqrt(),
+        hypsometric_curve,
+        hypsometric_integral: if max > min {
+            (mean - min) / (max - min)
+        } else {
+            0.0
+        },
+        slope_histogram: histogram(&slopes, (0.0, 90.0), bins),
+        mean_slope: slopes.iter().sum::<f32>() / n,
+        water_coverage: water.map_or(0.0, |w| w.iter().filter(|&&w| w).count() as f32 / n),
+        mean_roughness: rough.iter().sum::<f32>() / n,
+        max_roughness: value_range(&rough).1,
+    }
+}
+
+/// Counts of `values` in `bins` equal bins over `range`. Values outside the
+/// range go to the first or last bin.
+pub fn histogram(values: &[f32], (lo, hi): (f32, f32), bins: usize) -> Vec<usize> {
+    let mut counts = vec![0; bins];
+    for &v in values {
+        let t = if hi > lo { (v - lo) / (hi - lo) } else { 0.0 };
+        let bin = ((t * bins as f32) as usize).min(bins - 1);
+        counts[bin] += 1;
+    }
+    counts
+}
+
+/// Statistics as an indented JSON object.
+pub fn statistics_json(stats: &TerrainStatistics) -> String {
+    let list = |values: Vec<String>| format!("[{}]", values.join(", "));
+    format!(
+        "{{\n  \"min\": {:.3},\n  \"max\": {:.3},\n  \"mean\": {:.3},\n  \"std_dev\": {:.3},\n  \
+         \"hypsometric_curve\": {},\n  \"hypsometric_integral\": {:.3},\n  \
+         \"slope_histogram\": {},\n  \"mean_slope\": {:.3},\n  \"water_coverage\": {:.3},\n  \
+         \"mean_roughness\": {:.3},\n  \"max_roughness\": {:.3}\n}}",
+        stats.min,
+        stats.max,
+        stats.mean,
+        stats.std_dev,
+        list(
+            stats
+                .hypsometric_curve
+                .iter()
+                .map(|a| format!("{:.3}", a))
+                .collect()
+        ),
+        stats.hypsometric_integral,
+        list(
+            stats
+                .slope_histogram
+                .iter()
+                .map(|c| c.to_string())
+                .collect()
+        ),
+        stats.mean_slope,
+        stats.water_coverage,
+        stats.mean_roughness,
+        stats.max_roughness,
+    )
+}
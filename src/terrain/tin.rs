@@ -0,0 +1,457 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use super::Heightmap;
+use crate::utils::webgl::{Geometry, VertexAttrInfo, VertexAttrs};
+
+#[derive(Debug, Clone)]
+pub struct TinParams {
+    /// Stop once no heightmap vertex is further than this above or below the
+    /// network.
+    pub max_error: f32,
+    /// Stop once the network has this many vertices.
+    pub max_vertices: usize,
+}
+
+impl Default for TinParams {
+    fn default() -> TinParams {
+        TinParams {
+            max_error: 0.5,
+            max_vertices: usize::MAX,
+        }
+    }
+}
+
+/// Heightmap vertex coordinates `(i, j)`.
+type Point = (i64, i64);
+
+#[derive(Debug, Clone, Copy)]
+struct Triangle {
+    /// Counterclockwise corners, indices into the inserted points.
+    corners: [usize; 3],
+    /// Triangle across the edge opposite each corner.
+    neighbours: [Option<usize>; 3],
+}
+
+/// Heightmap vertex with the largest error within a triangle.
+struct Candidate {
+    error: f32,
+    point: Point,
+    triangle: usize,
+    /// Version of the triangle the error was computed for.
+    version: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        self.error.total_cmp(&other.error)
+    }
+}
+
+struct Tin<'a> {
+    map: &'a Heightmap,
+    points: Vec<Point>,
+    triangles: Vec<Triangle>,
+    versions: Vec<usize>,
+}
+
+/// Triangulated irregular network approximating a heightmap, built by greedy
+/// insertion (Garland & Heckbert, 1995): starting from the corners, the
+/// heightmap vertex furthest from the network is inserted until all are
+/// within `max_error`, keeping the triangulation Delaunay in the plane.
+/// Normals and texture coordinates are those of the heightmap.
+pub fn tin_geometry(map: &Heightmap, params: &TinParams) -> Geometry {
+    let tin = build(map, params);
+    let positions: Vec<_> = tin
+        .points
+        .iter()
+        .map(|&(i, j)| map.position(i as usize, j as usize))
+        .collect();
+    Geometry {
+        triangles: tin
+            .triangles
+            .iter()
+            .flat_map(|t| t.corners.iter().map(|&c| c as u32))
+            .collect(),
+        attributes: VertexAttrs::new(vec![
+            VertexAttrInfo::f32(
+                "position",
+                3,
+                positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+            ),
+            VertexAttrInfo::f32(
+                "normal",
+                3,
+                tin.points
+                    .iter()
+                    .flat_map(|&(i, j)| {
+                        let n = map.normal(i as usize, j as usize);
+                        vec![n.x, n.y, n.z]
+                    })
+                    .collect(),
+            ),
+            VertexAttrInfo::f32(
+                "texCoord",
+                2,
+                positions.iter().flat_map(|p| map.texcoord(p)).collect(),
+            ),
+        ]),
+    }
+}
+
+/// The network of `tin_geometry`, with its triangle adjacency.
+fn build<'a>(map: &'a Heightmap, params: &TinParams) -> Tin<'a> {
+    let last = map.size as i64 - 1;
+    let mut tin = Tin {
+        map,
+        points: vec![(0, 0), (last, 0), (0, last), (last, last)],
+        triangles: vec![
+            Triangle {
+                corners: [0, 1, 2],
+                neighbours: [Some(1), None, None],
+            },
+            Triangle {
+                corners: [2, 1, 3],
+                neighbours: [None, None, Some(0)],
+            },
+        ],
+        versions: vec![0, 0],
+    };
+
+    let mut heap = BinaryHeap::new();
+    heap.extend((0..2).filter_map(|t| tin.candidate(t)));
+    while tin.points.len() < params.max_vertices {
+        let candidate = match heap.pop() {
+            Some(c) => c,
+            None => break,
+        };
+        if candidate.version != tin.versions[candidate.triangle] {
+            continue;
+        }
+        if candidate.error <= params.max_error {
+            break;
+        }
+        let changed = tin.insert(candidate.point, candidate.triangle);
+        heap.extend(changed.into_iter().filter_map(|t| tin.candidate(t)));
+    }
+    tin
+}
+
+impl<'a> Tin<'a> {
+    fn height(&self, (i, j): Point) -> f32 {
+        self.map.height(i as usize, j as usize)
+    }
+
+    /// The worst approximated heightmap vertex in or on a triangle, if any
+    /// isn't one of its corners.
+    fn candidate(&self, triangle: usize) -> Option<Candidate> {
+        let [a, b, c] = self.triangles[triangle].corners.map(|k| self.points[k]);
+        let area = orient(a, b, c) as f32;
+        let (za, zb, zc) = (self.height(a), self.height(b), self.height(c));
+        let mut best: Option<(f32, Point)> = None;
+        for i in a.0.min(b.0).min(c.0)..=a.0.max(b.0).max(c.0) {
+            for j in a.1.min(b.1).min(c.1)..=a.1.max(b.1).max(c.1) {
+                let p = (i, j);
+                let (wa, wb, wc) = (orient(p, b, c), orient(a, p, c), orient(a, b, p));
+                if wa < 0 || wb < 0 || wc < 0 || p == a || p == b || p == c {
+                    continue;
+                }
+                let z = (wa as f32 * za + wb as f32 * zb + wc as f32 * zc) / area;
+                let error = (z - self.height(p)).abs();
+                if best.is_none_or(|(e, _)| error > e) {
+                    best = Some((error, p));
+                }
+            }
+        }
+        best.map(|(error, point)| Candidate {
+            error,
+            point,
+            triangle,
+            version: self.versions[triangle],
+        })
+    }
+
+    /// Inserts a point in or on the edge of a triangle and restores the
+    /// Delaunay property. Returns the triangles that changed.
+    fn insert(&mut self, point: Point, triangle: usize) -> Vec<usize> {
+        let p = self.points.len();
+        self.points.push(point);
+
+        // Triangles the point lies in, two if it's on an inner edge.
+        let t = self.triangles[triangle];
+        let mut cavity = vec![triangle];
+        let mut split_edge = None;
+        for k in 0..3 {
+            let (u, v) = (t.corners[(k + 1) % 3], t.corners[(k + 2) % 3]);
+            if orient(self.points[u], self.points[v], point) == 0 {
+                split_edge = Some((u, v));
+                cavity.extend(t.neighbours[k]);
+            }
+        }
+
+        // Edges around the cavity, counterclockwise, with the triangles
+        // beyond them.
+        let mut boundary = vec![];
+        for &c in cavity.iter() {
+            let t = self.triangles[c];
+            for k in 0..3 {
+                let (u, v) = (t.corners[(k + 1) % 3], t.corners[(k + 2) % 3]);
+                let on_split =
+                    split_edge.is_some_and(|(a, b)| (u, v) == (a, b) || (u, v) == (b, a));
+                if !on_split {
+                    boundary.push((u, v, t.neighbours[k]));
+                }
+            }
+        }
+        // On the outer border the cavity is open at the point, so start
+        // from the edge no other edge leads to.
+        let start = (0..boundary.len())
+            .find(|&e| !boundary.iter().any(|&(_, v, _)| v == boundary[e].0))
+            .unwrap_or(0);
+        let closed = boundary
+            .iter()
+            .all(|&(u, _, _)| boundary.iter().any(|&(_, v, _)| v == u));
+        let mut ordered = vec![boundary[start]];
+        while ordered.len() < boundary.len() {
+            let end = ordered[ordered.len() - 1].1;
+            ordered.push(*boundary.iter().find(|&&(u, _, _)| u == end).unwrap());
+        }
+
+        // Fan the cavity around the point, reusing its triangles' slots.
+        let fan: Vec<usize> = (0..ordered.len())
+            .map(|e| match cavity.get(e) {
+                Some(&c) => c,
+                None => {
+                    self.triangles.push(self.triangles[0]);
+                    self.versions.push(0);
+                    self.triangles.len() - 1
+                }
+            })
+            .collect();
+        let n = fan.len();
+        for (e, &(u, v, outer)) in ordered.iter().enumerate() {
+            let next = if closed || e + 1 < n {
+                Some(fan[(e + 1) % n])
+            } else {
+                None
+            };
+            let previous = if closed || e > 0 {
+                Some(fan[(e + n - 1) % n])
+            } else {
+                None
+            };
+            self.triangles[fan[e]] = Triangle {
+                corners: [p, u, v],
+                neighbours: [outer, next, previous],
+            };
+            if let Some(o) = outer {
+                self.relink(o, u, v, fan[e]);
+            }
+        }
+
+        let mut changed = fan.clone();
+        let mut stack = fan;
+        while let Some(t) = stack.pop() {
+            if let Some(o) = self.flip_if_illegal(t) {
+                stack.push(t);
+                stack.push(o);
+                changed.push(o);
+            }
+        }
+        changed.sort_unstable();
+        changed.dedup();
+        for &t in changed.iter() {
+            self.versions[t] += 1;
+        }
+        changed
+    }
+
+    /// Makes `triangle`'s neighbour across edge `(v, u)` be `to`.
+    fn relink(&mut self, triangle: usize, u: usize, v: usize, to: usize) {
+        let t = &mut self.triangles[triangle];
+        for k in 0..3 {
+            if (t.corners[(k + 1) % 3], t.corners[(k + 2) % 3]) == (v, u) {
+                t.neighbours[k] = Some(to);
+            }
+        }
+    }
+
+    /// Flips the edge of `(p, u, v)` opposite `p` if the triangle beyond it
+    /// has its far corner within the circumcircle. Returns that triangle.
+    fn flip_if_illegal(&mut self, triangle: usize) -> Option<usize> {
+        let t = self.triangles[triangle];
+        let [p, u, v] = t.corners;
+        let o = t.neighbours[0]?;
+        let other = self.triangles[o];
+        let k =
+            (0..3).find(|&k| other.corners[(k + 1) % 3] == v && other.corners[(k + 2) % 3] == u)?;
+        let q = other.corners[k];
+        let [pp, pu, pv, pq] = [p, u, v, q].map(|c| self.points[c]);
+        if in_circle(pp, pu, pv, pq) <= 0 {
+            return None;
+        }
+
+        // (p, u, v) and (q, v, u) become (p, u, q) and (p, q, v).
+        let beyond_uq = other.neighbours[(k + 1) % 3];
+        let beyond_qv = other.neighbours[(k + 2) % 3];
+        let (beyond_vp, beyond_pu) = (t.neighbours[1], t.neighbours[2]);
+        self.triangles[triangle] = Triangle {
+            corners: [p, u, q],
+            neighbours: [beyond_uq, Some(o), beyond_pu],
+        };
+        self.triangles[o] = Triangle {
+            corners: [p, q, v],
+            neighbours: [beyond_qv, beyond_vp, Some(triangle)],
+        };
+        if let Some(b) = beyond_uq {
+            self.relink(b, u, q, triangle);
+        }
+        if let Some(b) = beyond_vp {
+            self.relink(b, v, p, o);
+        }
+        Some(o)
+    }
+}
+
+/// Twice the signed area of `(a, b, c)`, positive if counterclockwise.
+fn orient(a: Point, b: Point, c: Point) -> i64 {
+    (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)
+}
+
+/// Positive if `d` lies within the circumcircle of counterclockwise
+/// `(a, b, c)`.
+fn in_circle(a: Point, b: Point, c: Point, d: Point) -> i64 {
+    let row = |p: Point| {
+        let (x, y) = (p.0 - d.0, p.1 - d.1);
+        (x, y, x * x + y * y)
+    };
+    let (a, b, c) = (row(a), row(b), row(c));
+    a.0 * (b.1 * c.2 - c.1 * b.2) - a.1 * (b.0 * c.2 - c.0 * b.2) + a.2 * (b.0 * c.1 - c.0 * b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::generate_heightfield;
+    use crate::utils::random::Rng;
+
+    fn heightmap() -> Heightmap {
+        Heightmap::from_positions(&generate_heightfield(&mut Rng::new(11)))
+    }
+
+    /// Corners of the edge opposite corner `k`.
+    fn edge(t: &Triangle, k: usize) -> (usize, usize) {
+        (t.corners[(k + 1) % 3], t.corners[(k + 2) % 3])
+    }
+
+    #[test]
+    fn is_delaunay() {
+        let map = heightmap();
+        let tin = build(&map, &TinParams::default());
+        for t in tin.triangles.iter() {
+            let [a, b, c] = t.corners.map(|k| tin.points[k]);
+            for n in t.neighbours.iter().flatten() {
+                let opposite = tin.triangles[*n]
+                    .corners
+                    .iter()
+                    .find(|k| !t.corners.contains(k))
+                    .unwrap();
+                assert!(in_circle(a, b, c, tin.points[*opposite]) <= 0);
+            }
+        }
+    }
+
+    #[test]
+    fn links_neighbours_both_ways() {
+        let map = heightmap();
+        let tin = build(&map, &TinParams::default());
+        for (id, t) in tin.triangles.iter().enumerate() {
+            assert!(
+                orient(
+                    tin.points[t.corners[0]],
+                    tin.points[t.corners[1]],
+                    tin.points[t.corners[2]]
+                ) > 0
+            );
+            for k in 0..3 {
+                let (u, v) = edge(t, k);
+                match t.neighbours[k] {
+                    Some(n) => {
+                        let other = &tin.triangles[n];
+                        let back = other.neighbours.iter().position(|&m| m == Some(id));
+                        // The neighbour walks the shared edge the other way.
+                        assert_eq!(back.map(|k| edge(other, k)), Some((v, u)));
+                    }
+                    // Only edges along the heightmap's border have no neighbour.
+                    None => {
+                        let (p, q) = (tin.points[u], tin.points[v]);
+                        let last = map.size as i64 - 1;
+                        assert!(
+                            (p.0 == q.0 && (p.0 == 0 || p.0 == last))
+                                || (p.1 == q.1 && (p.1 == 0 || p.1 == last))
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn approximates_within_max_error() {
+        let map = heightmap();
+        let params = TinParams {
+            max_error: 2.0,
+            ..TinParams::default()
+        };
+        let tin = build(&map, &params);
+        assert!(tin.points.len() < map.heights.len() / 4);
+        for i in 0..map.size as i64 {
+            for j in 0..map.size as i64 {
+                let p = (i, j);
+                let z = tin
+                    .triangles
+                    .iter()
+                    .find_map(|t| {
+                        let [a, b, c] = t.corners.map(|k| tin.points[k]);
+                        let (wa, wb, wc) = (orient(p, b, c), orient(a, p, c), orient(a, b, p));
+                        if wa < 0 || wb < 0 || wc < 0 {
+                            return None;
+                        }
+                        let (za, zb, zc) = (tin.height(a), tin.height(b), tin.height(c));
+                        Some(
+                            (wa as f32 * za + wb as f32 * zb + wc as f32 * zc)
+                                / orient(a, b, c) as f32,
+                        )
+                    })
+                    .unwrap();
+                let error = (z - map.height(i as usize, j as usize)).abs();
+                assert!(error <= params.max_error + 1e-3, "{:?}: {}", p, error);
+            }
+        }
+    }
+
+    #[test]
+    fn respects_max_vertices() {
+        let map = heightmap();
+        let params = TinParams {
+            max_error: 0.0,
+            max_vertices: 200,
+        };
+        let geometry = tin_geometry(&map, &params);
+        assert_eq!(geometry.vertex_count(), 200);
+    }
+}
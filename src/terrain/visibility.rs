@@ -0,0 +1,97 @@
+use nalgebra::{Vector2, Vector3};
+
+use super::{height_at, STEP};
+
+/// Distance between terrain samples along a sight line.
+const SAMPLE_SPACING: f32 = 0.5 * STEP;
+/// Terrain closer than this below a sight line doesn't block it, so that
+/// lines grazing the surface near their ends still count as clear.
+const CLEARANCE: f32 = 1e-2;
+
+/// Whether the segment from `a` to `b` stays above the terrain surface.
+/// Parts of the segment outside the grid never block it.
+pub fn line_of_sight(positions: &[Vector3<f32>], a: &Vector3<f32>, b: &Vector3<f32>) -> bool {
+    let samples = ((b - a).xy().norm() / SAMPLE_SPACING).ceil() as usize;
+    (1..samples).all(|s| {
+        let p = a.lerp(b, s as f32 / samples as f32);
+        height_at(positions, p.x, p.y).is_none_or(|z| z <= p.z + CLEARANCE)
+    })
+}
+
+/// Which vertices an observer standing `observer_height` above the ground at
+/// `observer` can see, counting a vertex as seen when a point
+/// `target_height` above it is in sight. `None` if the observer is off the
+/// map, with no ground to stand on.
+pub fn viewshed(
+    positions: &[Vector3<f32>],
+    observer: &Vector2<f32>,
+    observer_height: f32,
+    target_height: f32,
+) -> Option<Vec<bool>> {
+    let ground = height_at(positions, observer.x, observer.y)?;
+    let eye = Vector3::new(observer.x, observer.y, ground + observer_height);
+    Some(
+        positions
+            .iter()
+            .map(|p| {
+                line_of_sight(
+                    positions,
+                    &eye,
+                    &(p + Vector3::new(0.0, 0.0, target_height)),
+                )
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::RESOLUTION;
+
+    fn grid(z: impl Fn(f32) -> f32) -> Vec<Vector3<f32>> {
+        let mut positions = vec![];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                let (x, y) = (STEP * i as f32 - 250.0, STEP * j as f32 - 250.0);
+                positions.push(Vector3::new(x, y, z(x)));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn sees_everything_on_a_plane() {
+        let plane = grid(|_| 10.0);
+        let a = Vector3::new(-200.0, -100.0, 11.0);
+        let b = Vector3::new(150.0, 120.0, 10.0);
+        assert!(line_of_sight(&plane, &a, &b));
+        assert!(line_of_sight(&plane, &b, &a));
+        let seen = viewshed(&plane, &Vector2::new(0.0, 0.0), 2.0, 0.0).unwrap();
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn ridges_block_the_view() {
+        let ridge = grid(|x| 50.0 * f32::max(0.0, 1.0 - x.abs() / 20.0));
+        let (a, b) = (
+            Vector3::new(-100.0, 0.0, 2.0),
+            Vector3::new(100.0, 0.0, 2.0),
+        );
+        assert!(!line_of_sight(&ridge, &a, &b));
+        assert!(line_of_sight(&ridge, &a, &Vector3::new(-50.0, 30.0, 0.0)));
+        let over = Vector3::new(0.0, 0.0, 60.0);
+        assert!(line_of_sight(&ridge, &(a + over), &(b + over)));
+
+        let seen = viewshed(&ridge, &Vector2::new(-100.0, 0.0), 2.0, 0.0).unwrap();
+        let at = |x: f32| seen[((x + 250.0) / STEP) as usize * RESOLUTION as usize + 50];
+        assert!(at(-150.0) && at(-50.0));
+        assert!(!at(50.0) && !at(150.0));
+    }
+
+    #[test]
+    fn needs_an_observer_on_the_map() {
+        let plane = grid(|_| 0.0);
+        assert!(viewshed(&plane, &Vector2::new(400.0, 0.0), 2.0, 0.0).is_none());
+    }
+}
@@ -1,4 +1,10 @@
 pub mod geometry;
+pub mod image;
+pub mod obj;
+pub mod optimize;
+pub mod random;
+pub mod simplify;
+pub mod validate;
 pub mod web;
 pub mod webgl;
 
@@ -1,24 +1,278 @@
-use nalgebra::Vector3;
+use nalgebra::{Vector2, Vector3, Vector4};
+
+use super::webgl::{Geometry, VertexAttrInfo};
+
+/// Normal given to vertices no face contributes to.
+const UP: Vector3<f32> = Vector3::new(0.0, 0.0, 1.0);
+
+/// How much each incident face contributes to a vertex normal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalWeighting {
+    /// Every face counts the same.
+    Uniform,
+    /// Faces count in proportion to their area.
+    Area,
+    /// Faces count in proportion to their angle at the vertex (Thürmer &
+    /// Wüthrich, 1998), which doesn't depend on how the surface is
+    /// triangulated.
+    Angle,
+}
 
 pub fn compute_normals(
-    positions: &Vec<Vector3<f32>>,
-    triangles: &Vec<(u32, u32, u32)>,
+    positions: &[Vector3<f32>],
+    triangles: &[(u32, u32, u32)],
+    weighting: NormalWeighting,
 ) -> Vec<Vector3<f32>> {
     let mut normals: Vec<Vector3<f32>> =
         positions.iter().map(|_| Vector3::<f32>::zeros()).collect();
     for &(i, j, k) in triangles {
-        let i = i as usize;
-        let j = j as usize;
-        let k = k as usize;
-        let e1 = positions[j] - positions[i];
-        let e2 = positions[k] - positions[i];
+        let corners = [i as usize, j as usize, k as usize];
+        let e1 = positions[corners[1]] - positions[corners[0]];
+        let e2 = positions[corners[2]] - positions[corners[0]];
         let face_normal = e1.cross(&e2);
-        normals[i] += face_normal;
-        normals[j] += face_normal;
-        normals[k] += face_normal;
+        let unit_normal = match face_normal.try_normalize(f32::EPSILON) {
+            Some(n) => n,
+            None => continue,
+        };
+        for c in 0..3 {
+            let v = corners[c];
+            normals[v] += match weighting {
+                NormalWeighting::Uniform => unit_normal,
+                NormalWeighting::Area => face_normal,
+                NormalWeighting::Angle => {
+                    let a = positions[corners[(c + 1) % 3]] - positions[v];
+                    let b = positions[corners[(c + 2) % 3]] - positions[v];
+                    a.angle(&b) * unit_normal
+                }
+            };
+        }
     }
     for v in normals.iter_mut() {
-        v.normalize_mut();
+        *v = v.try_normalize(f32::EPSILON).unwrap_or(UP);
     }
     normals
 }
+
+/// Per-vertex tangents for tangent-space normal mapping, following the
+/// MikkTSpace conventions: the tangent points along increasing `u`, is
+/// orthogonal to the normal, and `w` holds the handedness such that the
+/// bitangent is `w * normal.cross(tangent)`. Face contributions are
+/// angle-weighted. Vertices whose texture mapping is degenerate get an
+/// arbitrary tangent orthogonal to the normal.
+pub fn compute_tangents(
+    positions: &[Vector3<f32>],
+    normals: &[Vector3<f32>],
+    texcoords: &[Vector2<f32>],
+    triangles: &[(u32, u32, u32)],
+) -> Vec<Vector4<f32>> {
+    let mut tangents = vec![Vector3::<f32>::zeros(); positions.len()];
+    let mut bitangents = vec![Vector3::<f32>::zeros(); positions.len()];
+    for &(i, j, k) in triangles {
+        let corners = [i as usize, j as usize, k as usize];
+        let e1 = positions[corners[1]] - positions[corners[0]];
+        let e2 = positions[corners[2]] - positions[corners[0]];
+        let d1 = texcoords[corners[1]] - texcoords[corners[0]];
+        let d2 = texcoords[corners[2]] - texcoords[corners[0]];
+        let r = d1.x * d2.y - d2.x * d1.y;
+        if r.abs() < f32::EPSILON {
+            continue;
+        }
+        let t = (e1 * d2.y - e2 * d1.y) / r;
+        let b = (e2 * d1.x - e1 * d2.x) / r;
+        for c in 0..3 {
+            let v = corners[c];
+            let to_next = positions[corners[(c + 1) % 3]] - positions[v];
+            let to_prev = positions[corners[(c + 2) % 3]] - positions[v];
+            let angle = to_next.angle(&to_prev);
+            let n = normals[v];
+            if let Some(t) = (t - n * n.dot(&t)).try_normalize(f32::EPSILON) {
+                tangents[v] += angle * t;
+            }
+            if let Some(b) = (b - n * n.dot(&b)).try_normalize(f32::EPSILON) {
+                bitangents[v] += angle * b;
+            }
+        }
+    }
+
+    normals
+        .iter()
+        .zip(tangents.iter().zip(bitangents.iter()))
+        .map(|(n, (t, b))| {
+            let t = (t - n * n.dot(t))
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(|| orthogonal(n));
+            let w = if n.cross(&t).dot(b) < 0.0 { -1.0 } else { 1.0 };
+            Vector4::new(t.x, t.y, t.z, w)
+        })
+        .collect()
+}
+
+/// Adds a `tangent` attribute to a triangle geometry with normals and
+/// float texture coordinates. Does nothing if either is missing.
+pub fn add_tangents_mut(geometry: &mut Geometry) {
+    let attrs = &geometry.attributes;
+    let floats = |name: &str| {
+        attrs
+            .get(name)
+            .and_then(|a| a.data.as_f32().map(|data| (a.size as usize, data)))
+    };
+    let ((size, position), (_, normal), (_, texcoord)) =
+        match (floats("position"), floats("normal"), floats("texCoord")) {
+            (Some(p), Some(n), Some(t)) => (p, n, t),
+            _ => return,
+        };
+    let positions: Vec<Vector3<f32>> = position
+        .chunks(size)
+        .map(|p| Vector3::new(p[0], p[1], p[2]))
+        .collect();
+    let normals: Vec<Vector3<f32>> = normal
+        .chunks(3)
+        .map(|n| Vector3::new(n[0], n[1], n[2]))
+        .collect();
+    let texcoords: Vec<Vector2<f32>> = texcoord
+        .chunks(2)
+        .map(|t| Vector2::new(t[0], t[1]))
+        .collect();
+    let triangles: Vec<(u32, u32, u32)> = geometry
+        .triangles
+        .chunks(3)
+        .map(|t| (t[0], t[1], t[2]))
+        .collect();
+
+    let tangents = compute_tangents(&positions, &normals, &texcoords, &triangles);
+    geometry.attributes.set(VertexAttrInfo::f32(
+        "tangent",
+        4,
+        tangents
+            .iter()
+            .flat_map(|t| vec![t.x, t.y, t.z, t.w])
+            .collect(),
+    ));
+}
+
+/// Octahedral encoding of a unit vector (Cigolle et al., 2014): the vector
+/// is projected onto the octahedron `|x| + |y| + |z| = 1`, whose lower half
+/// is folded over the upper one, giving a point in [-1, 1]². Decoded by
+/// `octahedralDecode` in `terrain_vertex.glsl`.
+pub fn octahedral_encode(n: &Vector3<f32>) -> Vector2<f32> {
+    let sign = |v: f32| if v >= 0.0 { 1.0 } else { -1.0 };
+    let p = n.xy() / (n.x.abs() + n.y.abs() + n.z.abs());
+    if n.z >= 0.0 {
+        p
+    } else {
+        Vector2::new((1.0 - p.y.abs()) * sign(p.x), (1.0 - p.x.abs()) * sign(p.y))
+    }
+}
+
+/// Some unit vector orthogonal to `n`.
+fn orthogonal(n: &Vector3<f32>) -> Vector3<f32> {
+    let axis = if n.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    (axis - n * n.dot(&axis)).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{
+        grid_triangles, heightfield_normals, terrain_geometry, TerrainNormals, RESOLUTION,
+    };
+
+    const WEIGHTINGS: [NormalWeighting; 3] = [
+        NormalWeighting::Uniform,
+        NormalWeighting::Area,
+        NormalWeighting::Angle,
+    ];
+
+    /// Grid of `terrain_geometry` lifted to the height `z(x, y)`.
+    fn grid(z: impl Fn(f32, f32) -> f32) -> Vec<Vector3<f32>> {
+        (0..RESOLUTION)
+            .flat_map(|i| (0..RESOLUTION).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let (x, y) = (5.0 * i as f32 - 250.0, 5.0 * j as f32 - 250.0);
+                Vector3::new(x, y, z(x, y))
+            })
+            .collect()
+    }
+
+    fn assert_close(
+        normals: &[Vector3<f32>],
+        expected: impl Fn(&Vector3<f32>) -> Vector3<f32>,
+        positions: &[Vector3<f32>],
+        tolerance: f32,
+    ) {
+        for (n, p) in normals.iter().zip(positions.iter()) {
+            let e = expected(p);
+            assert!((n - e).norm() < tolerance, "{} at {}: {}", n, p, e);
+        }
+    }
+
+    #[test]
+    fn plane_normals_are_exact() {
+        let positions = grid(|x, y| 0.3 * x - 0.2 * y + 7.0);
+        let expected = |_: &Vector3<f32>| Vector3::new(-0.3, 0.2, 1.0).normalize();
+        for weighting in WEIGHTINGS {
+            let normals = compute_normals(&positions, &grid_triangles(), weighting);
+            assert_close(&normals, expected, &positions, 1e-5);
+        }
+        assert_close(&heightfield_normals(&positions), expected, &positions, 1e-5);
+    }
+
+    #[test]
+    fn paraboloid_normals_are_close() {
+        let positions = grid(|x, y| (x * x + y * y) / 1000.0);
+        let expected = |p: &Vector3<f32>| Vector3::new(-p.x / 500.0, -p.y / 500.0, 1.0).normalize();
+        for weighting in WEIGHTINGS {
+            let normals = compute_normals(&positions, &grid_triangles(), weighting);
+            assert_close(&normals, expected, &positions, 0.01);
+        }
+        assert_close(&heightfield_normals(&positions), expected, &positions, 0.01);
+    }
+
+    #[test]
+    fn lone_vertices_face_up() {
+        let positions = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(2.0, 2.0, 2.0),
+            Vector3::new(5.0, 0.0, 3.0),
+        ];
+        // A degenerate triangle, and a vertex in no triangle.
+        for weighting in WEIGHTINGS {
+            let normals = compute_normals(&positions, &[(0, 1, 2)], weighting);
+            assert_eq!(normals, vec![UP; 4]);
+        }
+    }
+
+    #[test]
+    fn flat_grid_tangents_follow_u() {
+        let geometry = terrain_geometry(&grid(|_, _| 0.0), TerrainNormals::Analytic);
+        let floats = |name| {
+            geometry
+                .attributes
+                .get(name)
+                .unwrap()
+                .data
+                .as_f32()
+                .unwrap()
+        };
+        let (normals, texcoords) = (floats("normal"), floats("texCoord"));
+        let tangents = floats("tangent");
+        // Texture coordinates run u along x and v against y.
+        assert!(texcoords[2 * RESOLUTION as usize] > texcoords[0]);
+        assert!(texcoords[3] < texcoords[1]);
+        for (t, n) in tangents.chunks(4).zip(normals.chunks(3)) {
+            let (t, n) = (
+                Vector3::new(t[0], t[1], t[2]),
+                Vector3::from_column_slice(n),
+            );
+            assert!(t.dot(&n).abs() < 1e-6);
+            assert!((t - Vector3::x()).norm() < 1e-6, "{}", t);
+        }
+        // The bitangent `w * normal.cross(tangent)` follows v, towards -y.
+        assert!(tangents.chunks(4).all(|t| t[3] == -1.0));
+    }
+}
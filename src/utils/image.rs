@@ -0,0 +1,32 @@
+/// Binary PPM (P6) image from row-major RGB bytes.
+pub fn encode_ppm(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    bytes.extend_from_slice(rgb);
+    bytes
+}
+
+/// Binary PGM (P5) image from row-major grayscale bytes.
+pub fn encode_pgm(width: usize, height: usize, gray: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+    bytes.extend_from_slice(gray);
+    bytes
+}
+
+/// Blue-cyan-green-yellow-red color ramp for `t` in `[0, 1]`.
+pub fn color_ramp(t: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+    ];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let k = usize::min(x as usize, STOPS.len() - 2);
+    let f = x - k as f32;
+    let mut rgb = [0; 3];
+    for c in 0..3 {
+        rgb[c] = (255.0 * (STOPS[k][c] * (1.0 - f) + STOPS[k + 1][c] * f)).round() as u8;
+    }
+    rgb
+}
@@ -0,0 +1,96 @@
+use super::webgl::Geometry;
+
+/// Wavefront OBJ text of a triangle geometry, with its float `normal` and
+/// `texCoord` attributes if it has them. Texture coordinates are flipped to
+/// OBJ's convention of v growing upwards. OBJ has no tangents, so a float
+/// `tangent` attribute goes in `# vt4 x y z w` comment lines, one per vertex
+/// in order, which other readers skip; `w` is the handedness for the flipped
+/// texture coordinates. Returns `None` if the geometry has no float
+/// `position` attribute.
+pub fn encode_obj(geometry: &Geometry) -> Option<String> {
+    let float = |name: &str| {
+        let attr = geometry.attributes.get(name)?;
+        let size = attr.size as usize;
+        attr.data.as_f32().map(|data| (data, size))
+    };
+    let (positions, size) = float("position")?;
+    let normals = float("normal").filter(|&(_, size)| size == 3);
+    let texcoords = float("texCoord").filter(|&(_, size)| size == 2);
+    let tangents = float("tangent").filter(|&(_, size)| size == 4);
+
+    let mut obj = String::new();
+    for p in positions.chunks(size) {
+        obj += &format!("v {} {} {}\n", p[0], p[1], p[2]);
+    }
+    if let Some((texcoords, _)) = texcoords {
+        for t in texcoords.chunks(2) {
+            obj += &format!("vt {} {}\n", t[0], 1.0 - t[1]);
+        }
+    }
+    if let Some((normals, _)) = normals {
+        for n in normals.chunks(3) {
+            obj += &format!("vn {} {} {}\n", n[0], n[1], n[2]);
+        }
+    }
+    // Flipping v mirrors the bitangent.
+    if let Some((tangents, _)) = tangents {
+        for t in tangents.chunks(4) {
+            obj += &format!("# vt4 {} {} {} {}\n", t[0], t[1], t[2], -t[3]);
+        }
+    }
+    // OBJ indices start at 1, and every attribute shares the vertex index.
+    let corner = |v: u32| match (texcoords.is_some(), normals.is_some()) {
+        (false, false) => format!("{}", v + 1),
+        (true, false) => format!("{0}/{0}", v + 1),
+        (false, true) => format!("{0}//{0}", v + 1),
+        (true, true) => format!("{0}/{0}/{0}", v + 1),
+    };
+    for t in geometry.triangles.chunks(3) {
+        obj += &format!("f {} {} {}\n", corner(t[0]), corner(t[1]), corner(t[2]));
+    }
+    Some(obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{terrain_geometry, TerrainNormals, RESOLUTION};
+    use nalgebra::Vector3;
+
+    fn tangents(obj: &str) -> Vec<[f32; 4]> {
+        obj.lines()
+            .filter_map(|line| line.strip_prefix("# vt4 "))
+            .map(|line| {
+                let t: Vec<f32> = line.split(' ').map(|c| c.parse().unwrap()).collect();
+                [t[0], t[1], t[2], t[3]]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_tangents_with_their_handedness() {
+        let mut positions = vec![];
+        for i in 0..RESOLUTION {
+            for j in 0..RESOLUTION {
+                let (x, y) = (5.0 * i as f32 - 250.0, 5.0 * j as f32 - 250.0);
+                positions.push(Vector3::new(x, y, 0.01 * x * y));
+            }
+        }
+        let geometry = terrain_geometry(&positions, TerrainNormals::Analytic);
+        let expected = geometry
+            .attributes
+            .get("tangent")
+            .and_then(|a| a.data.as_f32())
+            .unwrap();
+        let written = tangents(&encode_obj(&geometry).unwrap());
+        assert_eq!(written.len(), positions.len());
+        for (w, e) in written.iter().zip(expected.chunks(4)) {
+            for c in 0..3 {
+                assert!((w[c] - e[c]).abs() < 1e-5);
+            }
+            assert_eq!(w[3], -e[3]);
+        }
+        // With v flipped, the texture's bitangent now runs along +y.
+        assert!(written.iter().all(|t| t[3] == 1.0));
+    }
+}
@@ -0,0 +1,317 @@
+use nalgebra::Vector3;
+
+use super::webgl::Geometry;
+
+/// Post-transform cache size ACMR is usually measured with.
+pub const FIFO_CACHE_SIZE: usize = 16;
+
+/// Size of the LRU cache `optimize_vertex_cache` models.
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Average cache miss ratio: vertex shader invocations per triangle with a
+/// FIFO post-transform cache of `cache_size` vertices. Ranges from about 0.5
+/// for ideal orderings of large grids to 3.
+pub fn acmr(triangles: &[u32], cache_size: usize) -> f32 {
+    let mut cache: Vec<u32> = vec![];
+    let mut misses = 0;
+    for &v in triangles {
+        if !cache.contains(&v) {
+            misses += 1;
+            cache.push(v);
+            if cache.len() > cache_size {
+                cache.remove(0);
+            }
+        }
+    }
+    misses as f32 / (triangles.len() / 3).max(1) as f32
+}
+
+/// Reorders triangles so that consecutive ones share vertices, following
+/// Forsyth's linear-speed vertex cache optimization (2006): triangles are
+/// emitted greedily by the score of their vertices, which favours those in
+/// a simulated LRU cache and those with few triangles left. Trailing
+/// indices that don't make a whole triangle are dropped.
+pub fn optimize_vertex_cache(triangles: &[u32], vertices: usize) -> Vec<u32> {
+    let count = triangles.len() / 3;
+    let mut remaining: Vec<Vec<usize>> = vec![vec![]; vertices];
+    for (t, corners) in triangles.chunks_exact(3).enumerate() {
+        for &v in corners {
+            remaining[v as usize].push(t);
+        }
+    }
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertices];
+    let mut vertex_scores: Vec<f32> = (0..vertices)
+        .map(|v| vertex_score(None, remaining[v].len()))
+        .collect();
+    let triangle_score = |t: usize, scores: &[f32]| {
+        triangles[3 * t..3 * t + 3]
+            .iter()
+            .map(|&v| scores[v as usize])
+            .sum::<f32>()
+    };
+    let mut emitted = vec![false; count];
+    let mut cache: Vec<u32> = vec![];
+    let mut output = Vec::with_capacity(triangles.len());
+    let mut next_unemitted = 0;
+
+    for _ in 0..count {
+        // Best triangle around the cached vertices, or else the first one
+        // not emitted yet.
+        let best = cache
+            .iter()
+            .flat_map(|&v| remaining[v as usize].iter().copied())
+            .max_by(|&a, &b| {
+                triangle_score(a, &vertex_scores).total_cmp(&triangle_score(b, &vertex_scores))
+            });
+        let t = match best {
+            Some(t) => t,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+        emitted[t] = true;
+        let corners = &triangles[3 * t..3 * t + 3];
+        output.extend_from_slice(corners);
+
+        for &v in corners {
+            remaining[v as usize].retain(|&other| other != t);
+            cache.retain(|&c| c != v);
+        }
+        for &v in corners.iter().rev() {
+            cache.insert(0, v);
+        }
+        for (k, &v) in cache.iter().enumerate() {
+            cache_position[v as usize] = if k < CACHE_SIZE { Some(k) } else { None };
+            vertex_scores[v as usize] =
+                vertex_score(cache_position[v as usize], remaining[v as usize].len());
+        }
+        cache.truncate(CACHE_SIZE);
+    }
+    output
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(k) if k < 3 => LAST_TRIANGLE_SCORE,
+        Some(k) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (k - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Reorders the clusters of a cache-optimized triangle list, split where the
+/// cache ordering jumps to a fresh part of the mesh, so that those facing
+/// away from the mesh's centre come first and tend to occlude the others
+/// (Sander et al., 2007). Keeps the input order if that would raise the ACMR
+/// by more than the factor `threshold`.
+pub fn optimize_overdraw(
+    triangles: &[u32],
+    positions: &[Vector3<f32>],
+    threshold: f32,
+) -> Vec<u32> {
+    let mut clusters: Vec<&[u32]> = vec![];
+    let mut cache: Vec<u32> = vec![];
+    let mut start = 0;
+    for (t, corners) in triangles.chunks_exact(3).enumerate() {
+        if t > start && corners.iter().all(|v| !cache.contains(v)) {
+            clusters.push(&triangles[3 * start..3 * t]);
+            start = t;
+        }
+        for &v in corners {
+            if !cache.contains(&v) {
+                cache.push(v);
+                if cache.len() > FIFO_CACHE_SIZE {
+                    cache.remove(0);
+                }
+            }
+        }
+    }
+    clusters.push(&triangles[3 * start..]);
+
+    // Area-weighted centroids and normals.
+    let moments = |triangles: &[u32]| {
+        let (mut centroid, mut normal, mut area) = (Vector3::zeros(), Vector3::zeros(), 0.0);
+        for corners in triangles.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| positions[corners[k] as usize]);
+            let n = (b - a).cross(&(c - a));
+            centroid += n.norm() * (a + b + c) / 3.0;
+            area += n.norm();
+            normal += n;
+        }
+        (centroid / area.max(f32::EPSILON), normal)
+    };
+    let (mesh_centroid, _) = moments(triangles);
+    let mut keyed: Vec<(f32, &[u32])> = clusters
+        .into_iter()
+        .map(|cluster| {
+            let (centroid, normal) = moments(cluster);
+            let facing = normal.try_normalize(f32::EPSILON).unwrap_or_default();
+            ((centroid - mesh_centroid).dot(&facing), cluster)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let output: Vec<u32> = keyed
+        .into_iter()
+        .flat_map(|(_, c)| c.iter().copied())
+        .collect();
+
+    if acmr(&output, FIFO_CACHE_SIZE) > threshold * acmr(triangles, FIFO_CACHE_SIZE) {
+        triangles.to_vec()
+    } else {
+        output
+    }
+}
+
+/// Renumbers vertices in the order triangles first use them, so that
+/// attribute fetches walk memory sequentially. Unused vertices go last.
+pub fn optimize_vertex_fetch_mut(geometry: &mut Geometry) {
+    let vertices = geometry.vertex_count();
+    let mut order = Vec::with_capacity(vertices);
+    let mut index = vec![u32::MAX; vertices];
+    for v in geometry.triangles.iter_mut() {
+        if index[*v as usize] == u32::MAX {
+            index[*v as usize] = order.len() as u32;
+            order.push(*v as usize);
+        }
+        *v = index[*v as usize];
+    }
+    order.extend((0..vertices).filter(|&v| index[v] == u32::MAX));
+    for attr in geometry.attributes.list.iter_mut() {
+        *attr = attr.select(&order);
+    }
+}
+
+/// Runs the vertex cache, overdraw and vertex fetch optimizations on a
+/// triangle geometry. The overdraw pass needs a float `position` attribute
+/// and may cost up to 5% of the ACMR.
+pub fn optimize_mut(geometry: &mut Geometry) {
+    geometry.triangles = optimize_vertex_cache(&geometry.triangles, geometry.vertex_count());
+    let positions: Option<Vec<Vector3<f32>>> = geometry.attributes.get("position").and_then(|a| {
+        let size = a.size as usize;
+        a.data.as_f32().map(|data| {
+            data.chunks(size)
+                .map(|p| Vector3::new(p[0], p[1], p[2]))
+                .collect()
+        })
+    });
+    if let Some(positions) = positions {
+        geometry.triangles = optimize_overdraw(&geometry.triangles, &positions, 1.05);
+    }
+    optimize_vertex_fetch_mut(geometry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{generate_heightfield, grid_triangles, terrain_geometry, TerrainNormals};
+    use crate::utils::{geometry::NormalWeighting, random::Rng};
+
+    /// Triangles as sorted corners, each rotated to start with its least
+    /// corner so that the winding is kept.
+    fn triangle_set<T: Ord + Copy>(triangles: impl Iterator<Item = [T; 3]>) -> Vec<[T; 3]> {
+        let mut set: Vec<[T; 3]> = triangles
+            .map(|mut t| {
+                let first = (0..3).min_by_key(|&k| t[k]).unwrap();
+                t.rotate_left(first);
+                t
+            })
+            .collect();
+        set.sort();
+        set
+    }
+
+    fn indexed(triangles: &[u32]) -> Vec<[u32; 3]> {
+        triangle_set(triangles.chunks(3).map(|t| [t[0], t[1], t[2]]))
+    }
+
+    /// Triangles by the bits of their corners' positions, which survive
+    /// renumbering.
+    fn positioned(geometry: &Geometry) -> Vec<[[u32; 3]; 3]> {
+        let positions = geometry
+            .attributes
+            .get("position")
+            .and_then(|a| a.data.as_f32())
+            .unwrap();
+        let corner = |v: u32| {
+            let p = &positions[3 * v as usize..3 * v as usize + 3];
+            [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]
+        };
+        triangle_set(
+            geometry
+                .triangles
+                .chunks(3)
+                .map(|t| [corner(t[0]), corner(t[1]), corner(t[2])]),
+        )
+    }
+
+    fn grid() -> Vec<u32> {
+        grid_triangles()
+            .iter()
+            .flat_map(|&(a, b, c)| [a, b, c])
+            .collect()
+    }
+
+    #[test]
+    fn vertex_cache_lowers_acmr() {
+        let triangles = grid();
+        let vertices = *triangles.iter().max().unwrap() as usize + 1;
+        let optimized = optimize_vertex_cache(&triangles, vertices);
+        assert!(
+            acmr(&optimized, FIFO_CACHE_SIZE) < acmr(&triangles, FIFO_CACHE_SIZE),
+            "{} {}",
+            acmr(&optimized, FIFO_CACHE_SIZE),
+            acmr(&triangles, FIFO_CACHE_SIZE)
+        );
+        assert_eq!(indexed(&optimized), indexed(&triangles));
+    }
+
+    #[test]
+    fn vertex_cache_drops_partial_triangles() {
+        let mut triangles = grid();
+        let whole = triangles.clone();
+        triangles.extend_from_slice(&[0, 1]);
+        let vertices = *triangles.iter().max().unwrap() as usize + 1;
+        let optimized = optimize_vertex_cache(&triangles, vertices);
+        assert_eq!(indexed(&optimized), indexed(&whole));
+    }
+
+    #[test]
+    fn overdraw_keeps_triangles() {
+        let positions = generate_heightfield(&mut Rng::new(3));
+        let triangles = optimize_vertex_cache(&grid(), positions.len());
+        let optimized = optimize_overdraw(&triangles, &positions, 1.05);
+        assert_eq!(indexed(&optimized), indexed(&triangles));
+        assert!(acmr(&optimized, FIFO_CACHE_SIZE) <= 1.05 * acmr(&triangles, FIFO_CACHE_SIZE));
+    }
+
+    #[test]
+    fn vertex_fetch_keeps_triangles() {
+        let positions = generate_heightfield(&mut Rng::new(3));
+        let mut geometry =
+            terrain_geometry(&positions, TerrainNormals::Mesh(NormalWeighting::Area));
+        geometry.triangles = optimize_vertex_cache(&geometry.triangles, geometry.vertex_count());
+        let before = positioned(&geometry);
+        optimize_vertex_fetch_mut(&mut geometry);
+        assert_eq!(positioned(&geometry), before);
+        // Vertices are numbered in order of first use.
+        let mut next = 0;
+        for &v in geometry.triangles.iter() {
+            assert!(v <= next);
+            next = next.max(v + 1);
+        }
+        assert_eq!(geometry.vertex_count(), positions.len());
+    }
+}
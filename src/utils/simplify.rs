@@ -0,0 +1,629 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use super::webgl::{Geometry, VertexAttrInfo, VertexAttrs, VertexData};
+
+/// Weight of the planes keeping border vertices on the border, relative to
+/// those of the faces.
+const BORDER_WEIGHT: f64 = 1000.0;
+
+#[derive(Debug, Clone)]
+pub struct SimplifyParams {
+    /// Stop once the geometry has at most this many triangles.
+    pub target_triangles: usize,
+    /// Largest error of a single collapse, roughly how far in world units it
+    /// moves the surface. Stops earlier if this is reached first.
+    pub max_error: f32,
+}
+
+impl Default for SimplifyParams {
+    fn default() -> SimplifyParams {
+        SimplifyParams {
+            target_triangles: 0,
+            max_error: 0.5,
+        }
+    }
+}
+
+/// Simplifies a triangle geometry by quadric error edge collapses (Garland &
+/// Heckbert, 1997) until it has `target_triangles` triangles or the next
+/// collapse would exceed `max_error`. Float attributes are interpolated
+/// along collapsed edges, others are taken from the surviving vertex.
+///
+/// Border vertices only move along the border. Vertices sharing a position
+/// but differing in another attribute, as along UV seams, never move, so
+/// both sides of a seam stay stitched together. Collapses that would flip a
+/// triangle or make the mesh non-manifold are skipped. Returns `None` if
+/// the geometry has no float `position` attribute.
+pub fn simplify(geometry: &Geometry, params: &SimplifyParams) -> Option<Geometry> {
+    let attrs = &geometry.attributes.list;
+    let position = geometry.attributes.get("position")?;
+    let size = position.size as usize;
+    let positions: Vec<Vector3<f64>> = position
+        .data
+        .as_f32()?
+        .chunks(size)
+        .map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+        .collect();
+    let mut floats: Vec<Option<Vec<f32>>> = attrs
+        .iter()
+        .map(|a| a.data.as_f32().map(|data| data.to_vec()))
+        .collect();
+
+    // Weld exact duplicates, then fix the vertices still sharing a position.
+    let mut welded = HashMap::new();
+    let weld: Vec<usize> = (0..positions.len())
+        .map(|v| {
+            let key: Vec<u32> = attrs
+                .iter()
+                .flat_map(|a| {
+                    let size = a.size as usize;
+                    (v * size..(v + 1) * size).map(move |k| component_bits(&a.data, k))
+                })
+                .collect();
+            *welded.entry(key).or_insert(v)
+        })
+        .collect();
+    let mut points = HashMap::new();
+    let mut fixed = vec![false; positions.len()];
+    for v in (0..positions.len()).filter(|&v| weld[v] == v) {
+        let key: Vec<u32> = (0..3)
+            .map(|k| component_bits(&position.data, v * size + k))
+            .collect();
+        if let Some(&w) = points.get(&key) {
+            fixed[v] = true;
+            fixed[w] = true;
+        } else {
+            points.insert(key, v);
+        }
+    }
+
+    let triangles: Vec<[usize; 3]> = geometry
+        .triangles
+        .chunks(3)
+        .map(|t| {
+            [
+                weld[t[0] as usize],
+                weld[t[1] as usize],
+                weld[t[2] as usize],
+            ]
+        })
+        .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+        .collect();
+    let mut mesh = Mesh::new(positions, triangles, fixed);
+
+    let mut heap = BinaryHeap::new();
+    let mut edges = HashSet::new();
+    for t in mesh.triangles.iter() {
+        for c in 0..3 {
+            let (a, b) = (t[c], t[(c + 1) % 3]);
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+    heap.extend(edges.into_iter().filter_map(|(u, v)| mesh.candidate(u, v)));
+
+    let max_cost = (params.max_error as f64).powi(2);
+    let mut live = mesh.triangles.len();
+    while live > params.target_triangles {
+        let Collapse { u, v, versions, .. } = match heap.pop() {
+            Some(c) => c,
+            None => break,
+        };
+        if versions != (mesh.versions[u], mesh.versions[v]) {
+            continue;
+        }
+        let shared = mesh.shared(u, v);
+        let placement = match mesh.placement(u, v, shared.len()) {
+            Some(p) => p,
+            None => continue,
+        };
+        if placement.cost > max_cost {
+            break;
+        }
+        if !mesh.link_condition(u, v, shared.len()) || mesh.flips(u, v, &placement.position) {
+            continue;
+        }
+
+        // The vertex the merged one is closer to survives.
+        let keep = if placement.t < 0.5 { u } else { v };
+        for (a, data) in attrs.iter().zip(floats.iter_mut()) {
+            if let Some(data) = data {
+                interpolate(data, a, u, v, keep, placement.t as f32);
+            }
+        }
+        live -= shared.len();
+        mesh.collapse(u, v, keep, placement.position, &shared);
+        heap.extend(
+            mesh.neighbours(keep)
+                .into_iter()
+                .filter_map(|w| mesh.candidate(keep, w)),
+        );
+    }
+
+    let mut used = vec![];
+    let mut index = vec![u32::MAX; mesh.positions.len()];
+    let mut triangles = vec![];
+    for (t, _) in mesh
+        .triangles
+        .iter()
+        .zip(mesh.removed.iter())
+        .filter(|(_, &removed)| !removed)
+    {
+        for &v in t.iter() {
+            if index[v] == u32::MAX {
+                index[v] = used.len() as u32;
+                used.push(v);
+            }
+            triangles.push(index[v]);
+        }
+    }
+    if let Some(data) = attrs
+        .iter()
+        .position(|a| a.glsl_name == "position")
+        .and_then(|k| floats[k].as_mut())
+    {
+        for &v in used.iter() {
+            let p = mesh.positions[v];
+            data[v * size..v * size + 3].copy_from_slice(&[p.x as f32, p.y as f32, p.z as f32]);
+        }
+    }
+    let list = attrs
+        .iter()
+        .zip(floats)
+        .map(|(a, floats)| match floats {
+            Some(data) => VertexAttrInfo {
+                glsl_name: a.glsl_name.clone(),
+                size: a.size,
+                normalized: a.normalized,
+                data: VertexData::F32(data),
+            }
+            .select(&used),
+            None => a.select(&used),
+        })
+        .collect();
+    Some(Geometry {
+        triangles,
+        attributes: VertexAttrs::new(list),
+    })
+}
+
+/// Candidate collapse of edge `(u, v)`, ordered so that the cheapest comes
+/// first out of a `BinaryHeap`.
+struct Collapse {
+    cost: f64,
+    u: usize,
+    v: usize,
+    /// Versions of `u` and `v` the cost was computed for.
+    versions: (usize, usize),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// Where a collapse puts the merged vertex. The other attributes are
+/// interpolated `t` of the way from `u` to `v`.
+struct Placement {
+    position: Vector3<f64>,
+    t: f64,
+    cost: f64,
+}
+
+struct Mesh {
+    positions: Vec<Vector3<f64>>,
+    quadrics: Vec<Matrix4<f64>>,
+    triangles: Vec<[usize; 3]>,
+    removed: Vec<bool>,
+    /// Live triangles around each vertex.
+    incident: Vec<Vec<usize>>,
+    /// Vertices that never move.
+    fixed: Vec<bool>,
+    border: Vec<bool>,
+    /// Bumped whenever a vertex changes, invalidating queued collapses.
+    versions: Vec<usize>,
+}
+
+impl Mesh {
+    fn new(positions: Vec<Vector3<f64>>, triangles: Vec<[usize; 3]>, fixed: Vec<bool>) -> Mesh {
+        let n = positions.len();
+        let mut incident = vec![vec![]; n];
+        let mut quadrics = vec![Matrix4::zeros(); n];
+        let mut edge_faces = HashMap::new();
+        for (id, t) in triangles.iter().enumerate() {
+            let normal = face_normal(&positions, t);
+            for c in 0..3 {
+                incident[t[c]].push(id);
+                if let Some(normal) = normal {
+                    quadrics[t[c]] += plane_quadric(&normal, &positions[t[0]], 1.0);
+                }
+                let (a, b) = (t[c], t[(c + 1) % 3]);
+                edge_faces
+                    .entry((a.min(b), a.max(b)))
+                    .or_insert_with(Vec::new)
+                    .push(normal);
+            }
+        }
+
+        // Penalize moving border vertices off planes through the border
+        // edges, perpendicular to their faces.
+        let mut border = vec![false; n];
+        for (&(a, b), normals) in edge_faces.iter() {
+            if normals.len() != 1 {
+                continue;
+            }
+            border[a] = true;
+            border[b] = true;
+            let edge = positions[b] - positions[a];
+            if let Some(n) = normals[0].and_then(|f| edge.cross(&f).try_normalize(f64::EPSILON)) {
+                let q = plane_quadric(&n, &positions[a], BORDER_WEIGHT);
+                quadrics[a] += q;
+                quadrics[b] += q;
+            }
+        }
+
+        Mesh {
+            positions,
+            quadrics,
+            removed: vec![false; triangles.len()],
+            triangles,
+            incident,
+            fixed,
+            border,
+            versions: vec![0; n],
+        }
+    }
+
+    /// Live triangles with edge `(u, v)`.
+    fn shared(&self, u: usize, v: usize) -> Vec<usize> {
+        self.incident[u]
+            .iter()
+            .copied()
+            .filter(|&id| self.triangles[id].contains(&v))
+            .collect()
+    }
+
+    fn neighbours(&self, v: usize) -> HashSet<usize> {
+        self.incident[v]
+            .iter()
+            .flat_map(|&id| self.triangles[id].iter().copied())
+            .filter(|&w| w != v)
+            .collect()
+    }
+
+    fn candidate(&self, u: usize, v: usize) -> Option<Collapse> {
+        let placement = self.placement(u, v, self.shared(u, v).len())?;
+        Some(Collapse {
+            cost: placement.cost,
+            u,
+            v,
+            versions: (self.versions[u], self.versions[v]),
+        })
+    }
+
+    /// Cheapest position for the merged vertex, or `None` if `(u, v)` must
+    /// not collapse.
+    fn placement(&self, u: usize, v: usize, shared: usize) -> Option<Placement> {
+        let (pu, pv) = (self.positions[u], self.positions[v]);
+        let (bu, bv) = (self.border[u], self.border[v]);
+        let candidates = match (self.fixed[u], self.fixed[v]) {
+            (true, true) => return None,
+            (true, false) => vec![pu],
+            (false, true) => vec![pv],
+            _ if shared == 0 => return None,
+            // An inner edge between two border vertices would pinch the
+            // mesh.
+            _ if bu && bv && shared > 1 => return None,
+            _ if bu && !bv => vec![pu],
+            _ if bv && !bu => vec![pv],
+            // Merging along the border at either end keeps it in place,
+            // whereas the plane penalties only keep it close.
+            _ if bu && bv => vec![pu, pv],
+            _ => {
+                let mut candidates = vec![pu, pv, (pu + pv) / 2.0];
+                if let Some(p) = optimal_position(&(self.quadrics[u] + self.quadrics[v])) {
+                    if (p - (pu + pv) / 2.0).norm() <= (pv - pu).norm() {
+                        candidates.push(p);
+                    }
+                }
+                candidates
+            }
+        };
+
+        let q = self.quadrics[u] + self.quadrics[v];
+        let cost = |p: &Vector3<f64>| {
+            let h = Vector4::new(p.x, p.y, p.z, 1.0);
+            h.dot(&(q * h)).max(0.0)
+        };
+        let position = candidates
+            .into_iter()
+            .min_by(|a, b| cost(a).total_cmp(&cost(b)))?;
+        let edge = pv - pu;
+        let t = if edge.norm_squared() > 0.0 {
+            ((position - pu).dot(&edge) / edge.norm_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        Some(Placement {
+            position,
+            t,
+            cost: cost(&position),
+        })
+    }
+
+    /// Whether `u` and `v` share no neighbours other than the opposite
+    /// corners of the triangles on their edge, so that collapsing it keeps
+    /// the mesh manifold.
+    fn link_condition(&self, u: usize, v: usize, shared: usize) -> bool {
+        let common = self.neighbours(u).intersection(&self.neighbours(v)).count();
+        common == shared
+    }
+
+    /// Whether moving `u` and `v` to `p` would flip or degenerate any of the
+    /// triangles not on their edge.
+    fn flips(&self, u: usize, v: usize, p: &Vector3<f64>) -> bool {
+        self.incident[u]
+            .iter()
+            .chain(self.incident[v].iter())
+            .map(|&id| self.triangles[id])
+            .filter(|t| !(t.contains(&u) && t.contains(&v)))
+            .any(|t| {
+                let before = face_normal(&self.positions, &t);
+                let moved: Vec<Vector3<f64>> = t
+                    .iter()
+                    .map(|&w| {
+                        if w == u || w == v {
+                            *p
+                        } else {
+                            self.positions[w]
+                        }
+                    })
+                    .collect();
+                let after = (moved[1] - moved[0])
+                    .cross(&(moved[2] - moved[0]))
+                    .try_normalize(f64::EPSILON);
+                match (before, after) {
+                    (Some(before), Some(after)) => before.dot(&after) < 0.0,
+                    (_, None) => true,
+                    (None, Some(_)) => false,
+                }
+            })
+    }
+
+    /// Merges `u` and `v` into `keep` at `position`, removing the `shared`
+    /// triangles.
+    fn collapse(
+        &mut self,
+        u: usize,
+        v: usize,
+        keep: usize,
+        position: Vector3<f64>,
+        shared: &[usize],
+    ) {
+        let gone = if keep == u { v } else { u };
+        for &id in shared.iter() {
+            self.removed[id] = true;
+        }
+        let removed = &self.removed;
+        for &id in shared.iter() {
+            for &w in self.triangles[id].iter() {
+                self.incident[w].retain(|&id| !removed[id]);
+            }
+        }
+        for id in std::mem::take(&mut self.incident[gone]) {
+            for corner in self.triangles[id].iter_mut() {
+                if *corner == gone {
+                    *corner = keep;
+                }
+            }
+            self.incident[keep].push(id);
+        }
+
+        self.positions[keep] = position;
+        self.quadrics[keep] = self.quadrics[u] + self.quadrics[v];
+        self.border[keep] = self.border[u] || self.border[v];
+        self.versions[u] += 1;
+        self.versions[v] += 1;
+    }
+}
+
+fn face_normal(positions: &[Vector3<f64>], t: &[usize; 3]) -> Option<Vector3<f64>> {
+    let e1 = positions[t[1]] - positions[t[0]];
+    let e2 = positions[t[2]] - positions[t[0]];
+    e1.cross(&e2).try_normalize(f64::EPSILON)
+}
+
+/// Quadric measuring the squared distance to the plane through `p` with
+/// unit normal `n`.
+fn plane_quadric(n: &Vector3<f64>, p: &Vector3<f64>, weight: f64) -> Matrix4<f64> {
+    let plane = Vector4::new(n.x, n.y, n.z, -n.dot(p));
+    plane * plane.transpose() * weight
+}
+
+/// Position minimizing a quadric, if there is a unique one.
+fn optimal_position(q: &Matrix4<f64>) -> Option<Vector3<f64>> {
+    let a = q.fixed_slice::<3, 3>(0, 0).into_owned();
+    if a.determinant().abs() < 1e-6 {
+        return None;
+    }
+    a.try_inverse()
+        .map(|inverse| -(inverse * q.fixed_slice::<3, 1>(0, 3)))
+}
+
+/// Interpolates vertex `keep` of a float attribute `t` of the way from `u`
+/// to `v`, renormalizing normals.
+fn interpolate(data: &mut [f32], attr: &VertexAttrInfo, u: usize, v: usize, keep: usize, t: f32) {
+    let size = attr.size as usize;
+    for k in 0..size {
+        let (a, b) = (data[u * size + k], data[v * size + k]);
+        data[keep * size + k] = a + t * (b - a);
+    }
+    if attr.glsl_name == "normal" && size == 3 {
+        let n = Vector3::from_column_slice(&data[keep * 3..keep * 3 + 3]);
+        if let Some(n) = n.try_normalize(f32::EPSILON) {
+            data[keep * 3..keep * 3 + 3].copy_from_slice(n.as_slice());
+        }
+    }
+}
+
+fn component_bits(data: &VertexData, k: usize) -> u32 {
+    match data {
+        VertexData::F32(data) => data[k].to_bits(),
+        VertexData::F16(data) | VertexData::U16(data) => data[k] as u32,
+        VertexData::U8(data) => data[k] as u32,
+        VertexData::I16(data) => data[k] as u16 as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{
+        compact_terrain_geometry, generate_heightfield, terrain_geometry, TerrainNormals,
+    };
+    use crate::utils::{geometry::NormalWeighting, random::Rng};
+
+    const NORMALS: TerrainNormals = TerrainNormals::Mesh(NormalWeighting::Area);
+
+    fn terrain() -> Geometry {
+        terrain_geometry(&generate_heightfield(&mut Rng::new(7)), NORMALS)
+    }
+
+    fn positions(geometry: &Geometry) -> Vec<Vector3<f32>> {
+        geometry
+            .attributes
+            .get("position")
+            .and_then(|a| a.data.as_f32())
+            .unwrap()
+            .chunks(3)
+            .map(Vector3::from_column_slice)
+            .collect()
+    }
+
+    #[test]
+    fn reaches_target_triangle_count() {
+        let params = SimplifyParams {
+            target_triangles: 2000,
+            max_error: f32::INFINITY,
+        };
+        let simplified = simplify(&terrain(), &params).unwrap();
+        let count = simplified.triangles.len() / 3;
+        // A collapse removes one or two triangles.
+        assert!((1999..=2000).contains(&count), "{} triangles", count);
+    }
+
+    #[test]
+    fn keeps_border_vertices_on_border() {
+        let geometry = terrain();
+        let (lo, hi) = positions(&geometry)
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), p| {
+                (lo.min(p.x).min(p.y), hi.max(p.x).max(p.y))
+            });
+        let params = SimplifyParams {
+            target_triangles: 500,
+            max_error: f32::INFINITY,
+        };
+        let simplified = simplify(&geometry, &params).unwrap();
+        let points = positions(&simplified);
+
+        let mut edges = HashMap::new();
+        for t in simplified.triangles.chunks(3) {
+            for c in 0..3 {
+                let (a, b) = (t[c], t[(c + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        let on_border = |p: &Vector3<f32>| {
+            [p.x, p.y]
+                .iter()
+                .any(|&c| (c - lo).abs() < 1e-3 || (c - hi).abs() < 1e-3)
+        };
+        for (&(a, b), _) in edges.iter().filter(|(_, &count)| count == 1) {
+            let (pa, pb) = (points[a as usize], points[b as usize]);
+            assert!(on_border(&pa) && on_border(&pb), "{} {}", pa, pb);
+            // Both ends on the same side of the square.
+            assert!(
+                (pa.x - pb.x).abs() < 1e-3 || (pa.y - pb.y).abs() < 1e-3,
+                "{} {}",
+                pa,
+                pb
+            );
+        }
+        // The corners survive, so the mesh still covers the whole square.
+        let area: f32 = simplified
+            .triangles
+            .chunks(3)
+            .map(|t| {
+                let (a, b, c) = (
+                    points[t[0] as usize],
+                    points[t[1] as usize],
+                    points[t[2] as usize],
+                );
+                0.5 * ((b - a).xy().perp(&(c - a).xy()))
+            })
+            .sum();
+        assert!((area - (hi - lo).powi(2)).abs() < 1.0, "area {}", area);
+    }
+
+    #[test]
+    fn never_flips_triangles() {
+        let params = SimplifyParams {
+            target_triangles: 300,
+            max_error: f32::INFINITY,
+        };
+        let simplified = simplify(&terrain(), &params).unwrap();
+        let points = positions(&simplified);
+        for t in simplified.triangles.chunks(3) {
+            let (a, b, c) = (
+                points[t[0] as usize],
+                points[t[1] as usize],
+                points[t[2] as usize],
+            );
+            // Every triangle of a heightfield faces up, though collapses onto
+            // the border can leave vertical slivers along it.
+            assert!((b - a).cross(&(c - a)).z >= 0.0, "{} {} {}", a, b, c);
+        }
+    }
+
+    #[test]
+    fn stops_at_max_error() {
+        let geometry = terrain();
+        let count = |max_error| {
+            let params = SimplifyParams {
+                target_triangles: 0,
+                max_error,
+            };
+            simplify(&geometry, &params).unwrap().triangles.len() / 3
+        };
+        let (fine, coarse) = (count(0.1), count(1.0));
+        assert!(0 < coarse && coarse < fine && fine < geometry.triangles.len() / 3);
+    }
+
+    #[test]
+    fn needs_float_positions() {
+        let positions = generate_heightfield(&mut Rng::new(7));
+        assert!(simplify(
+            &compact_terrain_geometry(&positions, NORMALS),
+            &SimplifyParams::default()
+        )
+        .is_none());
+    }
+}
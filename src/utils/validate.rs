@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use nalgebra::Vector3;
+
+use super::webgl::{Geometry, VertexAttrInfo, VertexData};
+
+/// Problem found by `Geometry::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeometryIssue {
+    /// More vertices than `u32` indices can address.
+    TooManyVertices(usize),
+    /// An attribute with a different number of vertices than the first one.
+    LengthMismatch {
+        attribute: String,
+        vertices: usize,
+    },
+    /// A NaN or infinite component.
+    NonFinite {
+        attribute: String,
+        vertex: usize,
+    },
+    /// Trailing indices that don't make up a whole triangle.
+    IncompleteTriangle,
+    IndexOutOfRange {
+        triangle: usize,
+        index: u32,
+    },
+    /// A triangle with a repeated corner or no area.
+    Degenerate {
+        triangle: usize,
+    },
+    /// A triangle wound against its vertex normals, or without normals
+    /// against most of its neighbours.
+    Flipped {
+        triangle: usize,
+    },
+}
+
+impl fmt::Display for GeometryIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeometryIssue::TooManyVertices(n) => write!(f, "{} vertices overflow u32 indices", n),
+            GeometryIssue::LengthMismatch {
+                attribute,
+                vertices,
+            } => write!(f, "attribute {} has {} vertices", attribute, vertices),
+            GeometryIssue::NonFinite { attribute, vertex } => {
+                write!(
+                    f,
+                    "attribute {} of vertex {} is not finite",
+                    attribute, vertex
+                )
+            }
+            GeometryIssue::IncompleteTriangle => write!(f, "index count is not a multiple of 3"),
+            GeometryIssue::IndexOutOfRange { triangle, index } => {
+                write!(f, "triangle {} has out of range index {}", triangle, index)
+            }
+            GeometryIssue::Degenerate { triangle } => {
+                write!(f, "triangle {} is degenerate", triangle)
+            }
+            GeometryIssue::Flipped { triangle } => write!(f, "triangle {} is flipped", triangle),
+        }
+    }
+}
+
+impl Geometry {
+    /// Problems that would make the geometry draw wrongly, or not at all.
+    /// Degenerate and flipped triangles are only detected with a float
+    /// `position` attribute.
+    pub fn validate(&self) -> Vec<GeometryIssue> {
+        let mut issues = vec![];
+        let vertices = self.vertex_count();
+        if vertices > u32::MAX as usize {
+            issues.push(GeometryIssue::TooManyVertices(vertices));
+        }
+        for attr in self.attributes.list.iter() {
+            let count = attr.data.len() / attr.size as usize;
+            if count != vertices || attr.data.len() % attr.size as usize != 0 {
+                issues.push(GeometryIssue::LengthMismatch {
+                    attribute: attr.glsl_name.clone(),
+                    vertices: count,
+                });
+            }
+            for v in 0..count {
+                if !is_finite(attr, v) {
+                    issues.push(GeometryIssue::NonFinite {
+                        attribute: attr.glsl_name.clone(),
+                        vertex: v,
+                    });
+                }
+            }
+        }
+        if !self.triangles.len().is_multiple_of(3) {
+            issues.push(GeometryIssue::IncompleteTriangle);
+        }
+        let vertices = self.complete_vertices();
+        let edges = self.directed_edges();
+        for (t, corners) in self.triangles.chunks_exact(3).enumerate() {
+            issues.extend(self.check_triangle(t, corners, vertices, &edges));
+        }
+        issues
+    }
+
+    /// Fixes what `validate` finds where possible, returning the issues
+    /// found. Attributes are cut to the shortest one, non-finite components
+    /// are zeroed, flipped triangles are turned around and other invalid
+    /// triangles, or any using a non-finite vertex, are removed.
+    pub fn repair_mut(&mut self) -> Vec<GeometryIssue> {
+        let issues = self.validate();
+        if issues.is_empty() {
+            return issues;
+        }
+
+        let vertices = self.complete_vertices();
+        let kept: Vec<usize> = (0..vertices).collect();
+        let mut finite = vec![true; vertices];
+        for attr in self.attributes.list.iter_mut() {
+            if attr.data.len() != vertices * attr.size as usize {
+                *attr = attr.select(&kept);
+            }
+            for (v, finite) in finite.iter_mut().enumerate() {
+                if !is_finite(attr, v) {
+                    *finite = false;
+                    zero(attr, v);
+                }
+            }
+        }
+
+        self.triangles.truncate(self.triangles.len() / 3 * 3);
+        let edges = self.directed_edges();
+        let mut triangles = Vec::with_capacity(self.triangles.len());
+        for (t, corners) in self.triangles.chunks_exact(3).enumerate() {
+            match self.check_triangle(t, corners, vertices, &edges) {
+                None if corners.iter().all(|&v| finite[v as usize]) => {
+                    triangles.extend_from_slice(corners)
+                }
+                Some(GeometryIssue::Flipped { .. }) => {
+                    triangles.extend_from_slice(&[corners[0], corners[2], corners[1]])
+                }
+                _ => {}
+            }
+        }
+        self.triangles = triangles;
+        issues
+    }
+
+    /// Vertices every attribute has.
+    fn complete_vertices(&self) -> usize {
+        self.attributes
+            .list
+            .iter()
+            .map(|a| a.data.len() / a.size as usize)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// How many triangles have each directed edge.
+    fn directed_edges(&self) -> HashMap<(u32, u32), usize> {
+        let mut edges = HashMap::new();
+        for corners in self.triangles.chunks_exact(3) {
+            for k in 0..3 {
+                *edges.entry((corners[k], corners[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        edges
+    }
+
+    fn check_triangle(
+        &self,
+        t: usize,
+        corners: &[u32],
+        vertices: usize,
+        edges: &HashMap<(u32, u32), usize>,
+    ) -> Option<GeometryIssue> {
+        if let Some(&index) = corners.iter().find(|&&v| v as usize >= vertices) {
+            return Some(GeometryIssue::IndexOutOfRange { triangle: t, index });
+        }
+        if corners[0] == corners[1] || corners[1] == corners[2] || corners[2] == corners[0] {
+            return Some(GeometryIssue::Degenerate { triangle: t });
+        }
+        let vectors = |name: &str| {
+            let attr = self.attributes.get(name)?;
+            let size = attr.size as usize;
+            let data = attr.data.as_f32().filter(|_| size >= 3)?;
+            Some(corners.iter().map(move |&v| {
+                let v = v as usize * size;
+                Vector3::new(data[v], data[v + 1], data[v + 2])
+            }))
+        };
+        let p: Vec<Vector3<f32>> = vectors("position")?.collect();
+        let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+        let face = e1.cross(&e2);
+        let scale = e1.norm_squared().max(e2.norm_squared());
+        // Also catches NaN positions.
+        if face.norm().partial_cmp(&(f32::EPSILON * scale)) != Some(std::cmp::Ordering::Greater) {
+            return Some(GeometryIssue::Degenerate { triangle: t });
+        }
+
+        // Normals alone can't tell for steep faces like skirts, whose
+        // vertices share the normals of the surface above, so a triangle
+        // its neighbours agree with isn't flipped.
+        let (mut against, mut along) = (0, 0);
+        for k in 0..3 {
+            let (a, b) = (corners[k], corners[(k + 1) % 3]);
+            against += edges.get(&(a, b)).map_or(0, |n| n - 1);
+            along += edges.get(&(b, a)).copied().unwrap_or(0);
+        }
+        let flipped = match vectors("normal") {
+            Some(n) => face.dot(&n.sum()) < 0.0 && along <= against,
+            None => against > along,
+        };
+        if flipped {
+            return Some(GeometryIssue::Flipped { triangle: t });
+        }
+        None
+    }
+}
+
+fn is_finite(attr: &VertexAttrInfo, v: usize) -> bool {
+    let size = attr.size as usize;
+    let range = v * size..(v + 1) * size;
+    match &attr.data {
+        VertexData::F32(data) => data[range].iter().all(|x| x.is_finite()),
+        // Half floats with all exponent bits set.
+        VertexData::F16(data) => data[range].iter().all(|&x| x & 0x7c00 != 0x7c00),
+        _ => true,
+    }
+}
+
+fn zero(attr: &mut VertexAttrInfo, v: usize) {
+    let size = attr.size as usize;
+    let range = v * size..(v + 1) * size;
+    match &mut attr.data {
+        VertexData::F32(data) => {
+            for x in data[range].iter_mut().filter(|x| !x.is_finite()) {
+                *x = 0.0;
+            }
+        }
+        VertexData::F16(data) => {
+            for x in data[range].iter_mut().filter(|x| **x & 0x7c00 == 0x7c00) {
+                *x = 0;
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::webgl::VertexAttrs;
+
+    const SIDE: u32 = 4;
+
+    /// Flat `SIDE * SIDE` grid facing up, with normals.
+    fn grid() -> Geometry {
+        let mut positions = vec![];
+        let mut normals = vec![];
+        for i in 0..SIDE {
+            for j in 0..SIDE {
+                positions.extend_from_slice(&[i as f32, j as f32, 0.0]);
+                normals.extend_from_slice(&[0.0, 0.0, 1.0]);
+            }
+        }
+        let at = |i: u32, j: u32| i * SIDE + j;
+        let mut triangles = vec![];
+        for i in 0..SIDE - 1 {
+            for j in 0..SIDE - 1 {
+                triangles.extend_from_slice(&[at(i, j), at(i + 1, j), at(i, j + 1)]);
+                triangles.extend_from_slice(&[at(i, j + 1), at(i + 1, j), at(i + 1, j + 1)]);
+            }
+        }
+        Geometry {
+            triangles,
+            attributes: VertexAttrs::new(vec![
+                VertexAttrInfo::f32("position", 3, positions),
+                VertexAttrInfo::f32("normal", 3, normals),
+            ]),
+        }
+    }
+
+    fn floats<'a>(geometry: &'a mut Geometry, name: &str) -> &'a mut Vec<f32> {
+        let attr = geometry
+            .attributes
+            .list
+            .iter_mut()
+            .find(|a| a.glsl_name == name)
+            .unwrap();
+        match &mut attr.data {
+            VertexData::F32(data) => data,
+            _ => unreachable!(),
+        }
+    }
+
+    /// An interior triangle, with neighbours across all its edges.
+    const INTERIOR: usize = 7;
+
+    #[test]
+    fn accepts_a_clean_grid() {
+        assert_eq!(grid().validate(), vec![]);
+    }
+
+    #[test]
+    fn finds_non_finite_positions() {
+        let mut geometry = grid();
+        floats(&mut geometry, "position")[3 * 5 + 2] = f32::NAN;
+        assert!(geometry.validate().contains(&GeometryIssue::NonFinite {
+            attribute: String::from("position"),
+            vertex: 5,
+        }));
+    }
+
+    #[test]
+    fn finds_out_of_range_indices() {
+        let mut geometry = grid();
+        geometry.triangles[3 * INTERIOR] = SIDE * SIDE;
+        assert!(geometry
+            .validate()
+            .contains(&GeometryIssue::IndexOutOfRange {
+                triangle: INTERIOR,
+                index: SIDE * SIDE,
+            }));
+    }
+
+    #[test]
+    fn finds_length_mismatches() {
+        let mut geometry = grid();
+        floats(&mut geometry, "normal").truncate(3 * (SIDE * SIDE - 1) as usize);
+        assert!(geometry
+            .validate()
+            .contains(&GeometryIssue::LengthMismatch {
+                attribute: String::from("normal"),
+                vertices: (SIDE * SIDE - 1) as usize,
+            }));
+    }
+
+    #[test]
+    fn finds_incomplete_triangles() {
+        let mut geometry = grid();
+        geometry.triangles.push(0);
+        assert_eq!(geometry.validate(), vec![GeometryIssue::IncompleteTriangle]);
+    }
+
+    #[test]
+    fn finds_repeated_corners() {
+        let mut geometry = grid();
+        geometry.triangles[3 * INTERIOR + 1] = geometry.triangles[3 * INTERIOR];
+        assert!(geometry
+            .validate()
+            .contains(&GeometryIssue::Degenerate { triangle: INTERIOR }));
+    }
+
+    #[test]
+    fn finds_zero_area_triangles() {
+        let mut geometry = grid();
+        // Corners on a line.
+        geometry.triangles.extend_from_slice(&[0, 1, 2]);
+        let last = geometry.triangles.len() / 3 - 1;
+        assert_eq!(
+            geometry.validate(),
+            vec![GeometryIssue::Degenerate { triangle: last }]
+        );
+    }
+
+    #[test]
+    fn finds_triangles_flipped_against_their_normals() {
+        let mut geometry = grid();
+        geometry.triangles.swap(3 * INTERIOR + 1, 3 * INTERIOR + 2);
+        assert_eq!(
+            geometry.validate(),
+            vec![GeometryIssue::Flipped { triangle: INTERIOR }]
+        );
+    }
+
+    #[test]
+    fn finds_triangles_flipped_against_their_neighbours() {
+        let mut geometry = grid();
+        geometry.attributes.list.retain(|a| a.glsl_name != "normal");
+        geometry.triangles.swap(3 * INTERIOR + 1, 3 * INTERIOR + 2);
+        assert_eq!(
+            geometry.validate(),
+            vec![GeometryIssue::Flipped { triangle: INTERIOR }]
+        );
+    }
+
+    #[test]
+    fn repairs_everything_it_finds() {
+        let mut geometry = grid();
+        // Vertex 0 is only used by triangle 0.
+        floats(&mut geometry, "position")[2] = f32::INFINITY;
+        floats(&mut geometry, "position").extend_from_slice(&[0.0, 0.0, 0.0]);
+        let interior = geometry.triangles[3 * INTERIOR..3 * INTERIOR + 3].to_vec();
+        geometry.triangles.swap(3 * INTERIOR + 1, 3 * INTERIOR + 2);
+        geometry.triangles[3] = SIDE * SIDE + 3;
+        geometry.triangles.extend_from_slice(&[0, 1, 2, 4, 4, 5, 6]);
+        assert!(!geometry.repair_mut().is_empty());
+        assert_eq!(geometry.validate(), vec![]);
+        // Triangles 0 and 1 and the added ones are gone, and the flipped
+        // one is turned back around.
+        let kept: Vec<&[u32]> = geometry.triangles.chunks(3).collect();
+        assert_eq!(kept.len(), 2 * ((SIDE - 1) * (SIDE - 1)) as usize - 2);
+        assert!(kept.contains(&&interior[..]));
+    }
+}
@@ -1,8 +1,10 @@
 use std::convert::TryInto;
 
-use js_sys::{Float32Array, Uint32Array};
+use js_sys::{Float32Array, Int16Array, Object, Uint16Array, Uint32Array, Uint8Array};
 use wasm_bindgen::JsValue;
-use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlShader, WebGlVertexArrayObject};
+use web_sys::{
+    WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlShader, WebGlVertexArrayObject,
+};
 
 use super::web::fetch_str;
 
@@ -30,37 +32,309 @@ pub struct Geometry {
     pub attributes: VertexAttrs,
 }
 
+impl Geometry {
+    pub fn vertex_count(&self) -> usize {
+        self.attributes
+            .list
+            .first()
+            .map_or(0, |a| a.data.len() / a.size as usize)
+    }
+}
+
+/// Vertex attributes bound by `setup_geometry`, each with a distinct name.
+/// The terrain shaders know `position`, `normal`, `texCoord` and `tangent`,
+/// and `height` and `octNormal` for compact grids.
 #[derive(Debug)]
 pub struct VertexAttrs {
-    pub position: VertexAttrInfo,
-    pub normal: Option<VertexAttrInfo>,
-    pub texcoord: Option<VertexAttrInfo>,
+    pub list: Vec<VertexAttrInfo>,
+}
+
+impl VertexAttrs {
+    pub fn new(list: Vec<VertexAttrInfo>) -> VertexAttrs {
+        VertexAttrs { list }
+    }
+
+    pub fn get(&self, glsl_name: &str) -> Option<&VertexAttrInfo> {
+        self.list.iter().find(|a| a.glsl_name == glsl_name)
+    }
+
+    /// Adds an attribute, replacing the one with the same name if any.
+    pub fn set(&mut self, attr: VertexAttrInfo) {
+        match self.list.iter_mut().find(|a| a.glsl_name == attr.glsl_name) {
+            Some(old) => *old = attr,
+            None => self.list.push(attr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentType {
+    F32,
+    /// Half float, stored as its bits.
+    F16,
+    U8,
+    U16,
+    I16,
+}
+
+impl ComponentType {
+    pub fn gl_type(self) -> u32 {
+        match self {
+            ComponentType::F32 => WebGl2RenderingContext::FLOAT,
+            ComponentType::F16 => WebGl2RenderingContext::HALF_FLOAT,
+            ComponentType::U8 => WebGl2RenderingContext::UNSIGNED_BYTE,
+            ComponentType::U16 => WebGl2RenderingContext::UNSIGNED_SHORT,
+            ComponentType::I16 => WebGl2RenderingContext::SHORT,
+        }
+    }
+
+    pub fn byte_size(self) -> usize {
+        match self {
+            ComponentType::F32 => 4,
+            ComponentType::F16 | ComponentType::U16 | ComponentType::I16 => 2,
+            ComponentType::U8 => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VertexData {
+    F32(Vec<f32>),
+    F16(Vec<u16>),
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    I16(Vec<i16>),
+}
+
+impl VertexData {
+    pub fn component_type(&self) -> ComponentType {
+        match self {
+            VertexData::F32(_) => ComponentType::F32,
+            VertexData::F16(_) => ComponentType::F16,
+            VertexData::U8(_) => ComponentType::U8,
+            VertexData::U16(_) => ComponentType::U16,
+            VertexData::I16(_) => ComponentType::I16,
+        }
+    }
+
+    /// Number of components.
+    pub fn len(&self) -> usize {
+        match self {
+            VertexData::F32(data) => data.len(),
+            VertexData::F16(data) | VertexData::U16(data) => data.len(),
+            VertexData::U8(data) => data.len(),
+            VertexData::I16(data) => data.len(),
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        match self {
+            VertexData::F32(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Appends the little-endian bytes of component `k`.
+    fn push_bytes(&self, k: usize, bytes: &mut Vec<u8>) {
+        match self {
+            VertexData::F32(data) => bytes.extend_from_slice(&data[k].to_le_bytes()),
+            VertexData::F16(data) | VertexData::U16(data) => {
+                bytes.extend_from_slice(&data[k].to_le_bytes())
+            }
+            VertexData::U8(data) => bytes.push(data[k]),
+            VertexData::I16(data) => bytes.extend_from_slice(&data[k].to_le_bytes()),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct VertexAttrInfo {
     pub glsl_name: String,
+    /// Components per vertex.
     pub size: i32,
-    pub data: Vec<f32>,
+    /// Whether integer components are mapped to [0, 1] (unsigned) or [-1, 1]
+    /// (signed) rather than converted to floats as they are.
+    pub normalized: bool,
+    pub data: VertexData,
+}
+
+impl VertexAttrInfo {
+    pub fn f32(glsl_name: &str, size: i32, data: Vec<f32>) -> VertexAttrInfo {
+        VertexAttrInfo {
+            glsl_name: String::from(glsl_name),
+            size,
+            normalized: false,
+            data: VertexData::F32(data),
+        }
+    }
+
+    pub fn f16(glsl_name: &str, size: i32, data: &[f32]) -> VertexAttrInfo {
+        VertexAttrInfo {
+            glsl_name: String::from(glsl_name),
+            size,
+            normalized: false,
+            data: VertexData::F16(data.iter().map(|&x| f16_bits(x)).collect()),
+        }
+    }
+
+    /// Values in [0, 1] as normalized 8-bit integers.
+    pub fn unorm8(glsl_name: &str, size: i32, data: &[f32]) -> VertexAttrInfo {
+        VertexAttrInfo {
+            glsl_name: String::from(glsl_name),
+            size,
+            normalized: true,
+            data: VertexData::U8(
+                data.iter()
+                    .map(|&x| (x.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Values in [0, 1] as normalized 16-bit integers.
+    pub fn unorm16(glsl_name: &str, size: i32, data: &[f32]) -> VertexAttrInfo {
+        VertexAttrInfo {
+            glsl_name: String::from(glsl_name),
+            size,
+            normalized: true,
+            data: VertexData::U16(
+                data.iter()
+                    .map(|&x| (x.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Values in [-1, 1] as normalized 16-bit integers.
+    pub fn snorm16(glsl_name: &str, size: i32, data: &[f32]) -> VertexAttrInfo {
+        VertexAttrInfo {
+            glsl_name: String::from(glsl_name),
+            size,
+            normalized: true,
+            data: VertexData::I16(
+                data.iter()
+                    .map(|&x| (x.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Bytes of one vertex's worth of components.
+    pub fn byte_size(&self) -> usize {
+        self.size as usize * self.data.component_type().byte_size()
+    }
+
+    /// The same attribute for the given vertices, in order.
+    pub fn select(&self, vertices: &[usize]) -> VertexAttrInfo {
+        fn select<T: Copy>(data: &[T], size: usize, vertices: &[usize]) -> Vec<T> {
+            vertices
+                .iter()
+                .flat_map(|&v| data[v * size..(v + 1) * size].iter().copied())
+                .collect()
+        }
+        let size = self.size as usize;
+        VertexAttrInfo {
+            glsl_name: self.glsl_name.clone(),
+            size: self.size,
+            normalized: self.normalized,
+            data: match &self.data {
+                VertexData::F32(data) => VertexData::F32(select(data, size, vertices)),
+                VertexData::F16(data) => VertexData::F16(select(data, size, vertices)),
+                VertexData::U8(data) => VertexData::U8(select(data, size, vertices)),
+                VertexData::U16(data) => VertexData::U16(select(data, size, vertices)),
+                VertexData::I16(data) => VertexData::I16(select(data, size, vertices)),
+            },
+        }
+    }
+}
+
+/// How `setup_geometry` lays out the attributes in GPU memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferLayout {
+    /// One tightly packed buffer per attribute.
+    Separate,
+    /// A single buffer with all attributes of a vertex next to each other,
+    /// each starting at a multiple of 4 bytes.
+    Interleaved,
 }
 
+/// Uploads a geometry into new buffers bound to a new vertex array. Returns
+/// both, since deleting the vertex array leaves its buffers allocated.
 pub fn setup_geometry(
     gl: &WebGl2RenderingContext,
     program: &WebGlProgram,
     geometry: &Geometry,
-) -> Result<WebGlVertexArrayObject, JsValue> {
+    layout: BufferLayout,
+) -> Result<(WebGlVertexArrayObject, Vec<WebGlBuffer>), JsValue> {
     let vao = gl
         .create_vertex_array()
         .ok_or("failed to create vertex array object")?;
     gl.bind_vertex_array(Some(&vao));
 
-    setup_attr(gl, program, &geometry.attributes.position)?;
+    // Attributes the program doesn't use are skipped.
+    let attrs: Vec<(u32, &VertexAttrInfo)> = geometry
+        .attributes
+        .list
+        .iter()
+        .filter_map(|attr| {
+            let loc = gl.get_attrib_location(program, &attr.glsl_name);
+            loc.try_into().ok().map(|loc| (loc, attr))
+        })
+        .collect();
+    let mut buffers = vec![];
+    match layout {
+        BufferLayout::Separate => {
+            for &(loc, attr) in attrs.iter() {
+                let buffer = gl.create_buffer().ok_or("failed to create buffer")?;
+                gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+                unsafe {
+                    let view: Object = match attr.data {
+                        VertexData::F32(ref data) => Float32Array::view(data).into(),
+                        VertexData::F16(ref data) | VertexData::U16(ref data) => {
+                            Uint16Array::view(data).into()
+                        }
+                        VertexData::U8(ref data) => Uint8Array::view(data).into(),
+                        VertexData::I16(ref data) => Int16Array::view(data).into(),
+                    };
+                    gl.buffer_data_with_array_buffer_view(
+                        WebGl2RenderingContext::ARRAY_BUFFER,
+                        &view,
+                        WebGl2RenderingContext::STATIC_DRAW,
+                    );
+                }
+                setup_attr(gl, loc, attr, 0, 0);
+                buffers.push(buffer);
+            }
+        }
+        BufferLayout::Interleaved => {
+            let used: Vec<&VertexAttrInfo> = attrs.iter().map(|&(_, attr)| attr).collect();
+            let (offsets, stride) = interleaved_offsets(&used);
+            let vertices = used.first().map_or(0, |a| a.data.len() / a.size as usize);
+            let mut bytes = Vec::with_capacity(vertices * stride);
+            for v in 0..vertices {
+                for attr in used.iter() {
+                    let size = attr.size as usize;
+                    for k in v * size..(v + 1) * size {
+                        attr.data.push_bytes(k, &mut bytes);
+                    }
+                    bytes.resize(bytes.len().next_multiple_of(4), 0);
+                }
+            }
 
-    if let Some(ref attr) = geometry.attributes.normal {
-        setup_attr(gl, program, attr)?;
-    }
-    if let Some(ref attr) = geometry.attributes.texcoord {
-        setup_attr(gl, program, attr)?;
+            let buffer = gl.create_buffer().ok_or("failed to create buffer")?;
+            gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+            unsafe {
+                gl.buffer_data_with_array_buffer_view(
+                    WebGl2RenderingContext::ARRAY_BUFFER,
+                    &Uint8Array::view(&bytes),
+                    WebGl2RenderingContext::STATIC_DRAW,
+                );
+            }
+            for (&(loc, attr), &offset) in attrs.iter().zip(offsets.iter()) {
+                setup_attr(gl, loc, attr, stride, offset);
+            }
+            buffers.push(buffer);
+        }
     }
 
     let index_buffer = gl.create_buffer().ok_or("failed to create buffer")?;
@@ -75,37 +349,118 @@ pub fn setup_geometry(
             WebGl2RenderingContext::STATIC_DRAW,
         );
     }
-    Ok(vao)
+    buffers.push(index_buffer);
+    Ok((vao, buffers))
 }
 
+/// Points the attribute at the buffer bound to `ARRAY_BUFFER`.
 fn setup_attr(
     gl: &WebGl2RenderingContext,
-    program: &WebGlProgram,
+    loc: u32,
     attr: &VertexAttrInfo,
-) -> Result<(), JsValue> {
-    let attr_loc: u32 = gl
-        .get_attrib_location(program, &attr.glsl_name)
-        .try_into()
-        .map_err(|_| "invalid attribute name")?;
-    let buffer = gl.create_buffer().ok_or("failed to create buffer")?;
-    gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
-    unsafe {
-        gl.buffer_data_with_array_buffer_view(
-            WebGl2RenderingContext::ARRAY_BUFFER,
-            &Float32Array::view(&attr.data),
-            WebGl2RenderingContext::STATIC_DRAW,
-        );
-    }
+    stride: usize,
+    offset: usize,
+) {
     gl.vertex_attrib_pointer_with_i32(
-        attr_loc,
+        loc,
         attr.size,
-        WebGl2RenderingContext::FLOAT,
-        false,
-        0,
-        0,
+        attr.data.component_type().gl_type(),
+        attr.normalized,
+        stride as i32,
+        offset as i32,
     );
-    gl.enable_vertex_attrib_array(attr_loc);
-    Ok(())
+    gl.enable_vertex_attrib_array(loc);
+}
+
+/// Byte offset of every attribute within an interleaved vertex, and the
+/// size of the vertex.
+fn interleaved_offsets(attrs: &[&VertexAttrInfo]) -> (Vec<usize>, usize) {
+    let mut offsets = vec![];
+    let mut stride = 0;
+    for attr in attrs {
+        offsets.push(stride);
+        stride += attr.byte_size().next_multiple_of(4);
+    }
+    (offsets, stride)
+}
+
+#[derive(Debug)]
+pub struct MemoryReport {
+    pub vertices: usize,
+    /// Bytes per vertex of every attribute, padding included.
+    pub attributes: Vec<(String, usize)>,
+    pub bytes_per_vertex: usize,
+    pub vertex_bytes: usize,
+    pub index_bytes: usize,
+}
+
+/// GPU memory `setup_geometry` would allocate for the geometry, counting
+/// every attribute.
+pub fn memory_report(geometry: &Geometry, layout: BufferLayout) -> MemoryReport {
+    let list = &geometry.attributes.list;
+    let padded = |attr: &VertexAttrInfo| match layout {
+        BufferLayout::Separate => attr.byte_size(),
+        BufferLayout::Interleaved => attr.byte_size().next_multiple_of(4),
+    };
+    let vertices = geometry.vertex_count();
+    let attributes: Vec<(String, usize)> = list
+        .iter()
+        .map(|attr| (attr.glsl_name.clone(), padded(attr)))
+        .collect();
+    let bytes_per_vertex = attributes.iter().map(|(_, bytes)| bytes).sum::<usize>();
+    MemoryReport {
+        vertices,
+        attributes,
+        bytes_per_vertex,
+        vertex_bytes: vertices * bytes_per_vertex,
+        index_bytes: 4 * geometry.triangles.len(),
+    }
+}
+
+/// Side-by-side comparison of labelled memory reports, one line each.
+pub fn memory_report_text(reports: &[(&str, MemoryReport)]) -> String {
+    reports
+        .iter()
+        .map(|(label, report)| {
+            let attributes: Vec<String> = report
+                .attributes
+                .iter()
+                .map(|(name, bytes)| format!("{} {}", name, bytes))
+                .collect();
+            format!(
+                "{}: {} vertices of {} B ({}), {} KiB vertices + {} KiB indices\n",
+                label,
+                report.vertices,
+                report.bytes_per_vertex,
+                attributes.join(", "),
+                report.vertex_bytes / 1024,
+                report.index_bytes / 1024
+            )
+        })
+        .collect()
+}
+
+/// Bits of the half float nearest to `x`.
+fn f16_bits(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = (bits >> 16) & 0x8000;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    let half = if exponent >= 31 {
+        // Overflow, infinity or NaN.
+        0x7c00 | if x.is_nan() { 0x200 } else { 0 }
+    } else if exponent <= 0 {
+        if exponent < -10 {
+            0
+        } else {
+            let m = (mantissa | 0x80_0000) >> (1 - exponent);
+            (m + 0x1000) >> 13
+        }
+    } else {
+        // A carry out of the mantissa correctly bumps the exponent.
+        ((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1)
+    };
+    (sign | half) as u16
 }
 
 fn link_shaders(